@@ -0,0 +1,51 @@
+-- The schema as it was before migrations were introduced.
+--
+-- Databases created back then already have this, so everything here has to be
+-- safe to run on top of it. Later migrations only ever run on top of the ones
+-- before them.
+
+CREATE TABLE IF NOT EXISTS users (
+    user_id             SERIAL PRIMARY KEY,
+    username            TEXT NOT NULL UNIQUE,
+    hash                TEXT NOT NULL,
+    sc_oauth_token      TEXT,
+    sc_client_id        TEXT,
+    liked_track_ids     BIGINT[] NOT NULL,
+    playlist_ids        BIGINT[] NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS soundcloudusers (
+    sc_user_id      BIGINT PRIMARY KEY,
+    avatar_url      TEXT,
+    full_name       TEXT NOT NULL,
+    username        TEXT NOT NULL,
+    permalink_url   TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS tracks (
+    track_id        BIGINT PRIMARY KEY,
+    sc_user_id      BIGINT NOT NULL references soundcloudusers(sc_user_id),
+    length_ms       BIGINT NOT NULL,
+    created_at      TEXT NOT NULL,
+    title           TEXT NOT NULL,
+    description     TEXT NOT NULL,
+    likes_count     BIGINT NOT NULL,
+    playback_count  BIGINT NOT NULL,
+    artwork_url     TEXT,
+    permalink_url   TEXT NOT NULL,
+    download_url    TEXT
+);
+
+CREATE TABLE IF NOT EXISTS playlists (
+    playlist_id     BIGINT PRIMARY KEY,
+    sc_user_id      BIGINT NOT NULL references soundcloudusers(sc_user_id),
+    track_ids       BIGINT[] NOT NULL,
+    num_tracks      BIGINT NOT NULL,
+    length_ms       BIGINT NOT NULL,
+    created_at      TEXT NOT NULL,
+    title           TEXT NOT NULL,
+    permalink_url   TEXT NOT NULL,
+    description     TEXT NOT NULL,
+    likes_count     BIGINT NOT NULL,
+    is_album        BOOLEAN NOT NULL
+);
@@ -0,0 +1,18 @@
+-- Scrapes recorded as jobs, at most one of which can be queued or running for
+-- each user at a time.
+
+CREATE TABLE scrape_jobs (
+    job_id                  SERIAL PRIMARY KEY,
+    user_id                 INTEGER NOT NULL references users(user_id),
+    status                  TEXT NOT NULL,
+    num_recent_likes        BIGINT,
+    num_recent_playlists    BIGINT,
+    created_at              TIMESTAMPTZ NOT NULL DEFAULT now(),
+    started_at              TIMESTAMPTZ,
+    finished_at             TIMESTAMPTZ,
+    error                   TEXT
+);
+
+CREATE UNIQUE INDEX scrape_jobs_one_active_per_user
+    ON scrape_jobs (user_id)
+    WHERE status IN ('queued', 'running');
@@ -0,0 +1,4 @@
+-- How each scrape job decides how much to scrape. Jobs recorded before this
+-- were all full scrapes.
+
+ALTER TABLE scrape_jobs ADD COLUMN mode TEXT NOT NULL DEFAULT 'full';
@@ -0,0 +1,5 @@
+-- How often each user's data is scraped automatically.
+
+ALTER TABLE users
+    ADD COLUMN scrape_schedule TEXT NOT NULL DEFAULT 'off',
+    ADD COLUMN next_scheduled_scrape_at TIMESTAMPTZ;
@@ -0,0 +1,9 @@
+-- When each track was first seen among a user's likes, and when it was unliked.
+
+CREATE TABLE like_history (
+    user_id         INTEGER NOT NULL references users(user_id),
+    track_id        BIGINT NOT NULL,
+    first_seen      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    removed_at      TIMESTAMPTZ,
+    PRIMARY KEY (user_id, track_id)
+);
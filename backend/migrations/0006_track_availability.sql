@@ -0,0 +1,6 @@
+-- Whether each archived track could still be found on SoundCloud when it was
+-- last checked.
+
+ALTER TABLE tracks
+    ADD COLUMN availability TEXT NOT NULL DEFAULT 'available',
+    ADD COLUMN last_verified_at TIMESTAMPTZ;
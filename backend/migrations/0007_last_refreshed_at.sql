@@ -0,0 +1,11 @@
+-- When the metadata of each track, playlist and SoundCloud user was last
+-- refreshed by a scrape.
+
+ALTER TABLE tracks
+    ADD COLUMN last_refreshed_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+ALTER TABLE playlists
+    ADD COLUMN last_refreshed_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+ALTER TABLE soundcloudusers
+    ADD COLUMN last_refreshed_at TIMESTAMPTZ NOT NULL DEFAULT now();
@@ -0,0 +1,23 @@
+-- The play, like and track counts of tracks and playlists as of every scrape.
+-- A scrape stores each track or playlist's counts once, however many times it
+-- came across it.
+
+CREATE TABLE track_stats_snapshots (
+    track_id        BIGINT NOT NULL references tracks(track_id),
+    scraped_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    playback_count  BIGINT NOT NULL,
+    likes_count     BIGINT NOT NULL
+);
+
+CREATE UNIQUE INDEX track_stats_snapshots_track_id
+    ON track_stats_snapshots (track_id, scraped_at);
+
+CREATE TABLE playlist_stats_snapshots (
+    playlist_id     BIGINT NOT NULL references playlists(playlist_id),
+    scraped_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    likes_count     BIGINT NOT NULL,
+    num_tracks      BIGINT NOT NULL
+);
+
+CREATE UNIQUE INDEX playlist_stats_snapshots_playlist_id
+    ON playlist_stats_snapshots (playlist_id, scraped_at);
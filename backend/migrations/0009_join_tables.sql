@@ -0,0 +1,78 @@
+-- Moves likes and playlist membership out of array columns into join tables.
+
+CREATE TABLE user_likes (
+    user_id         INTEGER NOT NULL references users(user_id),
+    track_id        BIGINT NOT NULL references tracks(track_id),
+    -- When the user liked the track on SoundCloud
+    liked_at        TIMESTAMPTZ,
+    -- When a scrape first saw the track among the user's likes
+    first_seen      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- Where the track is in the user's likes, starting at 0 for the most
+    -- recent one. NULL for likes that were removed.
+    position        INTEGER,
+    -- When a scrape first noticed that the track was no longer liked
+    removed_at      TIMESTAMPTZ,
+    PRIMARY KEY (user_id, track_id)
+);
+
+CREATE INDEX user_likes_position ON user_likes (user_id, position);
+
+CREATE TABLE user_playlists (
+    user_id         INTEGER NOT NULL references users(user_id),
+    playlist_id     BIGINT NOT NULL references playlists(playlist_id),
+    -- 'owned', 'liked' or 'unknown'
+    relation        TEXT NOT NULL,
+    PRIMARY KEY (user_id, playlist_id)
+);
+
+CREATE TABLE playlist_tracks (
+    playlist_id     BIGINT NOT NULL references playlists(playlist_id),
+    -- Not a foreign key, so that a playlist's track list can be stored in full
+    -- even if some of its tracks aren't archived
+    track_id        BIGINT NOT NULL,
+    position        INTEGER NOT NULL,
+    PRIMARY KEY (playlist_id, position)
+);
+
+CREATE INDEX playlist_tracks_track_id ON playlist_tracks (track_id);
+
+-- The like history covers every like that was seen since it was introduced
+INSERT INTO user_likes (user_id, track_id, first_seen, removed_at)
+SELECT h.user_id, h.track_id, h.first_seen, h.removed_at
+FROM like_history h
+WHERE h.track_id IN (SELECT track_id FROM tracks);
+
+-- The arrays are what was liked last. Their order is meaningless, so positions
+-- are left for the next scrape to fill in.
+INSERT INTO user_likes (user_id, track_id)
+SELECT DISTINCT u.user_id, l.track_id
+FROM users u, unnest(u.liked_track_ids) AS l(track_id)
+WHERE l.track_id IN (SELECT track_id FROM tracks)
+ON CONFLICT (user_id, track_id) DO UPDATE SET removed_at = NULL;
+
+-- Likes that the history still counts as current but that were cleared from
+-- the arrays since
+DELETE FROM user_likes l
+USING users u
+WHERE l.user_id = u.user_id
+    AND l.removed_at IS NULL
+    AND NOT (l.track_id = ANY(u.liked_track_ids));
+
+-- We didn't use to keep track of which playlists a user made and which they
+-- liked, so that stays unknown until the next scrape
+INSERT INTO user_playlists (user_id, playlist_id, relation)
+SELECT DISTINCT u.user_id, p.playlist_id, 'unknown'
+FROM users u, unnest(u.playlist_ids) AS p(playlist_id)
+WHERE p.playlist_id IN (SELECT playlist_id FROM playlists);
+
+INSERT INTO playlist_tracks (playlist_id, track_id, position)
+SELECT p.playlist_id, t.track_id, (t.ord - 1)::INTEGER
+FROM playlists p, unnest(p.track_ids) WITH ORDINALITY AS t(track_id, ord);
+
+ALTER TABLE users
+    DROP COLUMN liked_track_ids,
+    DROP COLUMN playlist_ids;
+
+ALTER TABLE playlists DROP COLUMN track_ids;
+
+DROP TABLE like_history;
@@ -0,0 +1,5 @@
+-- SoundCloud's timestamps used to be stored as the text they came in as.
+
+ALTER TABLE tracks ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at::TIMESTAMPTZ;
+
+ALTER TABLE playlists ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at::TIMESTAMPTZ;
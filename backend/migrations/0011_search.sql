@@ -0,0 +1,16 @@
+-- Indexes for full-text search over the archive.
+--
+-- The search route has to query with exactly these expressions for postgres to
+-- use the indexes.
+
+CREATE INDEX tracks_search ON tracks USING GIN (
+    (setweight(to_tsvector('english', title), 'A') || setweight(to_tsvector('english', description), 'B'))
+);
+
+CREATE INDEX playlists_search ON playlists USING GIN (
+    (setweight(to_tsvector('english', title), 'A') || setweight(to_tsvector('english', description), 'B'))
+);
+
+CREATE INDEX soundcloudusers_search ON soundcloudusers USING GIN (
+    (to_tsvector('simple', username || ' ' || full_name))
+);
@@ -0,0 +1,14 @@
+-- Audio files of archived tracks, which live in the blob store.
+
+CREATE TABLE track_audio (
+    track_id        BIGINT PRIMARY KEY references tracks(track_id),
+    -- The hex-encoded SHA-256 hash of the audio
+    hash            TEXT NOT NULL,
+    -- Relative to the root of the blob store
+    path            TEXT NOT NULL,
+    size_bytes      BIGINT NOT NULL,
+    content_type    TEXT NOT NULL,
+    archived_at     TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX track_audio_hash ON track_audio (hash);
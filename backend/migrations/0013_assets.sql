@@ -0,0 +1,16 @@
+-- Artwork and avatars mirrored from SoundCloud's CDN into the blob store.
+--
+-- Many URLs can point at the same image, which is only stored once.
+
+CREATE TABLE assets (
+    url             TEXT PRIMARY KEY,
+    -- The hex-encoded SHA-256 hash of the image
+    hash            TEXT NOT NULL,
+    -- Relative to the root of the blob store
+    path            TEXT NOT NULL,
+    size_bytes      BIGINT NOT NULL,
+    content_type    TEXT NOT NULL,
+    mirrored_at     TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX assets_hash ON assets (hash);
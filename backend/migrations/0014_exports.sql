@@ -0,0 +1,15 @@
+-- The latest export bundle of each user's archive, kept in the blob store.
+
+CREATE TABLE exports (
+    user_id         INTEGER PRIMARY KEY references users(user_id),
+    -- The job that made the bundle
+    job_id          INTEGER NOT NULL references scrape_jobs(job_id),
+    -- The hex-encoded SHA-256 hash of the bundle
+    hash            TEXT NOT NULL,
+    -- Relative to the root of the blob store
+    path            TEXT NOT NULL,
+    size_bytes      BIGINT NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX exports_hash ON exports (hash);
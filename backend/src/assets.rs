@@ -0,0 +1,49 @@
+use super::*;
+
+/// The URL that a mirrored asset with the given hash is served from
+pub fn local_asset_url(hash: &str) -> String {
+    format!("/api/assets/{}", hash)
+}
+
+/// Downloads the images at the given URLs into the blob store, skipping the
+/// ones that are already mirrored.
+///
+/// Mirroring is best-effort: images that fail to download are logged and
+/// left for the next scrape to try again.
+pub fn mirror_assets(db: &DbPool, store: &dyn BlobStore, urls: &[String]) -> Result<(), Error> {
+    let urls = {
+        let mut conn = db.get()?;
+        Asset::unmirrored_urls(&mut conn, urls)?
+    };
+
+    for url in urls {
+        if let Err(e) = mirror_asset(db, store, &url) {
+            eprintln!("Failed to mirror asset {}: {:?}", url, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Downloads the image at the given URL into the blob store.
+fn mirror_asset(db: &DbPool, store: &dyn BlobStore, url: &str) -> Result<(), Error> {
+    let response = ureq::get(url).call();
+    if let Some(e) = response.synthetic_error() {
+        return Err(Error::RequestError(e.to_string()));
+    }
+    if !response.ok() {
+        return Err(Error::HttpError(response.status()));
+    }
+
+    let content_type = response.content_type().to_string();
+    let blob = store.put(&mut response.into_reader())?;
+
+    let mut conn = db.get()?;
+    Asset {
+        url: url.into(),
+        hash: blob.hash,
+        path: blob.path,
+        size_bytes: blob.size_bytes,
+        content_type
+    }.create_new(&mut *conn)
+}
@@ -0,0 +1,61 @@
+use super::*;
+
+use std::sync::atomic::AtomicBool;
+
+/// Downloads the audio of every track the given job's user currently likes
+/// into the blob store, skipping tracks whose audio is already archived.
+///
+/// Tracks that SoundCloud has no downloadable audio for are skipped. Each
+/// track's audio is recorded as soon as it's stored, so whatever was archived
+/// before the job failed or was cancelled is kept. An "AudioArchivingProgress"
+/// SSE event is sent after each track.
+pub fn archive_liked_audio(
+    db: &DbPool,
+    store: &dyn BlobStore,
+    job: &ScrapeJob,
+    cancelled: &AtomicBool
+) -> Result<(), Error> {
+    let (user, track_ids) = {
+        let mut conn = db.get()?;
+        let user = User::load_id(&mut conn, job.user_id)?;
+        let track_ids = user.liked_track_ids_without_audio(&mut conn)?;
+
+        (user, track_ids)
+    };
+    let api = ScApi::for_user(&user)?;
+
+    for (i, track_id) in track_ids.iter().enumerate() {
+        check_cancelled(cancelled)?;
+
+        if let Some(mut audio) = api.track_audio(*track_id)? {
+            let blob = store.put(&mut audio.reader)?;
+
+            let mut conn = db.get()?;
+            TrackAudio {
+                track_id: *track_id,
+                hash: blob.hash,
+                path: blob.path,
+                size_bytes: blob.size_bytes,
+                content_type: audio.content_type
+            }.create_new(&mut *conn)?;
+        }
+
+        // We don't really care about errors here
+        let _ = SSE.push(
+            user.user_id,
+            "update",
+            &SseEvent::AudioArchivingProgress {
+                processed: i + 1,
+                total: track_ids.len()
+            }
+        );
+    }
+
+    let _ = SSE.push(
+        user.user_id,
+        "update",
+        &SseEvent::Complete
+    );
+
+    Ok(())
+}
@@ -0,0 +1,294 @@
+use super::*;
+
+use sha2::{Digest, Sha256};
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Where blobs are stored unless `BLOB_STORE_DIR` says otherwise
+const DEFAULT_BLOB_STORE_DIR: &str = "blobs";
+
+/// How old an unreferenced blob has to be before `gc-blobs` deletes it
+pub const GC_MIN_BLOB_AGE_HOURS: i64 = 24;
+
+/// Used to give every file that's being written a unique name
+static NEXT_TEMP_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// A content-addressed store of blobs.
+///
+/// Every blob is stored under the SHA-256 hash of its contents, so storing the
+/// same contents twice only keeps one copy around. Implementations must make
+/// sure a blob that can be found is never half-written.
+pub trait BlobStore: Send + Sync {
+    /// Reads everything from the given reader into a new blob.
+    fn put(&self, reader: &mut dyn Read) -> Result<StoredBlob, Error>;
+
+    /// Opens the blob with the given hash for reading.
+    ///
+    /// Fails with an `io::ErrorKind::NotFound` error if there is no such blob.
+    fn open(&self, hash: &str) -> Result<Box<dyn BlobReader>, Error>;
+
+    /// Removes the blob with the given hash, if there is one.
+    fn delete(&self, hash: &str) -> Result<(), Error>;
+
+    /// Lists every blob in the store.
+    fn list(&self) -> Result<Vec<BlobInfo>, Error>;
+}
+
+/// The blob store shared by the routes and the background jobs
+pub type SharedBlobStore = Arc<dyn BlobStore>;
+
+/// Something a blob can be read from
+pub trait BlobReader: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> BlobReader for T {}
+
+/// A blob that was put into a `BlobStore`
+pub struct StoredBlob {
+    /// The hex-encoded SHA-256 hash of the blob's contents
+    pub hash: String,
+    /// Where the blob is stored, relative to the root of the store
+    pub path: String,
+    /// The size of the blob in bytes
+    pub size_bytes: i64
+}
+
+/// A blob found when listing a `BlobStore`
+#[derive(Debug)]
+pub struct BlobInfo {
+    /// The hex-encoded SHA-256 hash of the blob's contents
+    pub hash: String,
+    /// When the blob was last written
+    pub last_modified: DateTime<Utc>
+}
+
+/// Creates the blob store chosen by the `BLOB_STORE` environment variable.
+///
+/// `fs` (the default) keeps blobs on disk, see `FsBlobStore::from_env`, and
+/// `s3` keeps them in an S3-compatible bucket, see `S3BlobStore::from_env`.
+pub fn blob_store_from_env() -> Result<SharedBlobStore, Error> {
+    match env::var("BLOB_STORE").as_ref().map(String::as_str) {
+        Err(_) | Ok("fs") => Ok(Arc::new(FsBlobStore::from_env()?)),
+        Ok("s3") => Ok(Arc::new(S3BlobStore::from_env()?)),
+        Ok(other) => Err(Error::BlobStoreError(format!("unknown blob store {:?}", other)))
+    }
+}
+
+/// Whether the given string could be the hash of a blob
+///
+/// Hashes often come from places like URLs, and are used to build paths and
+/// keys, so they should be checked with this first.
+pub fn is_blob_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Where the blob with the given hash is stored, relative to the root of a
+/// store.
+///
+/// Blobs are spread over subdirectories named after the first two characters
+/// of their hash to keep directories from getting huge.
+pub fn blob_path(hash: &str) -> String {
+    format!("{}/{}", &hash[..2], hash)
+}
+
+/// A unique name for a temporary file
+pub fn temp_file_name() -> String {
+    format!(
+        "{}-{}",
+        std::process::id(),
+        NEXT_TEMP_FILE.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// Copies everything from the given reader into the given writer, returning
+/// the hex-encoded SHA-256 hash of what was copied and its size in bytes.
+pub fn copy_hashed(reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<(String, i64)> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 64 * 1024];
+    let mut size_bytes = 0;
+
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        };
+
+        hasher.input(&buf[..n]);
+        writer.write_all(&buf[..n])?;
+        size_bytes += n as i64;
+    }
+
+    Ok((hex::encode(hasher.result()), size_bytes))
+}
+
+/// A blob store that keeps its blobs in a directory on disk.
+///
+/// Blobs are written to a temporary file first and only moved into place once
+/// they are complete.
+pub struct FsBlobStore {
+    root: PathBuf
+}
+
+impl FsBlobStore {
+    /// Creates a store that keeps its blobs in the given directory, creating
+    /// the directory if needed.
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, Error> {
+        let root = root.into();
+        fs::create_dir_all(root.join("tmp"))?;
+
+        Ok(Self { root })
+    }
+
+    /// Creates a store in the directory given by `BLOB_STORE_DIR`, or in
+    /// `blobs` if that isn't set.
+    pub fn from_env() -> Result<Self, Error> {
+        Self::new(env::var("BLOB_STORE_DIR").unwrap_or_else(|_| DEFAULT_BLOB_STORE_DIR.into()))
+    }
+}
+
+impl BlobStore for FsBlobStore {
+    fn put(&self, reader: &mut dyn Read) -> Result<StoredBlob, Error> {
+        let temp_path = self.root.join("tmp").join(temp_file_name());
+
+        let written = File::create(&temp_path).and_then(|mut file| {
+            let written = copy_hashed(reader, &mut file)?;
+            file.sync_all()?;
+
+            Ok(written)
+        });
+        let (hash, size_bytes) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                return Err(e.into());
+            }
+        };
+
+        let path = blob_path(&hash);
+        let full_path = self.root.join(&path);
+        fs::create_dir_all(full_path.parent().unwrap())?;
+        fs::rename(&temp_path, &full_path)?;
+
+        Ok(StoredBlob {
+            hash,
+            path,
+            size_bytes
+        })
+    }
+
+    fn open(&self, hash: &str) -> Result<Box<dyn BlobReader>, Error> {
+        Ok(Box::new(File::open(self.root.join(blob_path(hash)))?))
+    }
+
+    fn delete(&self, hash: &str) -> Result<(), Error> {
+        match fs::remove_file(self.root.join(blob_path(hash))) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(())
+        }
+    }
+
+    fn list(&self) -> Result<Vec<BlobInfo>, Error> {
+        let mut blobs = vec![];
+
+        for dir in fs::read_dir(&self.root)? {
+            let dir = dir?;
+            // Skips the directory of temporary files, among other things
+            if dir.file_name().len() != 2 || !dir.file_type()?.is_dir() {
+                continue;
+            }
+
+            for file in fs::read_dir(dir.path())? {
+                let file = file?;
+                let hash = match file.file_name().into_string() {
+                    Ok(hash) if is_blob_hash(&hash) => hash,
+                    _ => continue
+                };
+
+                blobs.push(BlobInfo {
+                    hash,
+                    last_modified: file.metadata()?.modified()?.into()
+                });
+            }
+        }
+
+        Ok(blobs)
+    }
+}
+
+/// What was found by `verify_blobs`
+#[derive(Debug, Default)]
+pub struct BlobReport {
+    /// Hashes of blobs whose contents no longer match their hash
+    pub corrupt: Vec<String>,
+    /// Hashes that the database refers to but that aren't in the store
+    pub missing: Vec<String>
+}
+
+/// The hashes of every blob the database refers to
+fn referenced_blob_hashes(db: &DbPool) -> Result<HashSet<String>, Error> {
+    let mut conn = db.get()?;
+
+    Ok(conn
+        .query("SELECT hash FROM track_audio UNION SELECT hash FROM assets UNION SELECT hash FROM exports", &[])?
+        .iter()
+        .map(|r| r.get(0))
+        .collect())
+}
+
+/// Checks that every blob in the store still matches its hash, and that every
+/// blob the database refers to is in the store.
+///
+/// Nothing is changed; what's wrong is only reported.
+pub fn verify_blobs(db: &DbPool, store: &dyn BlobStore) -> Result<BlobReport, Error> {
+    let mut report = BlobReport::default();
+    let mut stored = HashSet::new();
+
+    for blob in store.list()? {
+        let (hash, _) = copy_hashed(&mut store.open(&blob.hash)?, &mut io::sink())?;
+        if hash != blob.hash {
+            report.corrupt.push(blob.hash.clone());
+        }
+
+        stored.insert(blob.hash);
+    }
+
+    report.missing = referenced_blob_hashes(db)?
+        .into_iter()
+        .filter(|hash| !stored.contains(hash))
+        .collect();
+    report.missing.sort();
+
+    Ok(report)
+}
+
+/// Deletes every blob that the database doesn't refer to, returning the hashes
+/// of the deleted blobs.
+///
+/// Blobs are put into the store before the rows referring to them are
+/// written, so blobs written less than `min_age` ago are left alone to avoid
+/// deleting ones that are about to be referred to.
+pub fn collect_garbage(
+    db: &DbPool,
+    store: &dyn BlobStore,
+    min_age: chrono::Duration
+) -> Result<Vec<String>, Error> {
+    let cutoff = Utc::now() - min_age;
+    // Listed before the references are loaded, so that anything put after
+    // the references were loaded isn't considered
+    let blobs = store.list()?;
+    let referenced = referenced_blob_hashes(db)?;
+
+    let mut deleted = vec![];
+    for blob in blobs {
+        if blob.last_modified <= cutoff && !referenced.contains(&blob.hash) {
+            store.delete(&blob.hash)?;
+            deleted.push(blob.hash);
+        }
+    }
+
+    Ok(deleted)
+}
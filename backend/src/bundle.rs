@@ -0,0 +1,787 @@
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use orange_zest::api::common::Track as ScTrack;
+use orange_zest::api::likes::Likes;
+use orange_zest::api::playlists::{Playlist as ScPlaylist, Playlists};
+use postgres::GenericClient;
+use serde::de::DeserializeOwned;
+
+use super::*;
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+
+/// Where the media files of an export bundle are kept within it
+const MEDIA_DIR: &str = "media";
+
+/// The files of an export bundle that aren't media
+const BUNDLE_JSON_FILES: [&str; 5] = [
+    "manifest.json",
+    "likes.json",
+    "tracks.json",
+    "playlists.json",
+    "artists.json"
+];
+
+/// The first bytes of every gzip file, and so of every export bundle
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The largest JSON file an import reads, in bytes.
+///
+/// JSON is read into memory whole, and a small compressed bundle can hold a
+/// huge JSON file, so it's capped separately from the size of the upload.
+const MAX_IMPORT_JSON_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Everything in a user's archive, ready to be put into an export bundle
+struct ExportContents {
+    likes: Vec<ExportedLike>,
+    tracks: Vec<TrackInfoLong>,
+    playlists: Vec<ExportedPlaylist>,
+    artists: Vec<ScUserInfo>,
+    media: Vec<ExportedMedia>
+}
+
+/// Bundles up the archive of the given job's user and stores the bundle in the
+/// blob store as the user's latest export.
+///
+/// The bundle is put together in a temporary file first, so nothing replaces
+/// the user's previous export unless the job gets all the way through. An
+/// "ExportProgress" SSE event is sent after each media file is added.
+pub fn export_archive(
+    db: &DbPool,
+    store: &dyn BlobStore,
+    job: &ScrapeJob,
+    cancelled: &AtomicBool
+) -> Result<(), Error> {
+    let (user, contents) = {
+        let mut conn = db.get()?;
+        let user = User::load_id(&mut conn, job.user_id)?;
+        let contents = load_export_contents(&mut conn, &user)?;
+
+        (user, contents)
+    };
+    let manifest = ExportManifest {
+        schema_version: EXPORT_SCHEMA_VERSION,
+        exported_at: Utc::now(),
+        user: UserInfo {
+            user_id: user.user_id,
+            username: user.username.clone()
+        },
+        media: contents.media
+    };
+
+    let spool_path = env::temp_dir().join(format!("crate-export-{}", temp_file_name()));
+    let written = File::create(&spool_path)
+        .map_err(Error::from)
+        .and_then(|file| {
+            let mtime = manifest.exported_at.timestamp() as u64;
+            let mut bundle = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+            append_json(&mut bundle, "manifest.json", &manifest, mtime)?;
+            append_json(&mut bundle, "likes.json", &contents.likes, mtime)?;
+            append_json(&mut bundle, "tracks.json", &contents.tracks, mtime)?;
+            append_json(&mut bundle, "playlists.json", &contents.playlists, mtime)?;
+            append_json(&mut bundle, "artists.json", &contents.artists, mtime)?;
+
+            // The same file can be listed more than once, but is only added once
+            let mut added = HashSet::new();
+            let files: Vec<&ExportedMedia> = manifest.media
+                .iter()
+                .filter(|m| added.insert(&m.hash))
+                .collect();
+
+            for (i, media) in files.iter().enumerate() {
+                check_cancelled(cancelled)?;
+
+                let mut blob = store.open(&media.hash)?;
+                let len = blob.seek(SeekFrom::End(0))?;
+                blob.seek(SeekFrom::Start(0))?;
+                append_file(&mut bundle, &media.path, len, blob, mtime)?;
+
+                // We don't really care about errors here
+                let _ = SSE.push(
+                    user.user_id,
+                    "update",
+                    &SseEvent::ExportProgress {
+                        added: i + 1,
+                        total: files.len()
+                    }
+                );
+            }
+
+            bundle.into_inner()?.finish()?.sync_all()?;
+            Ok(())
+        })
+        .and_then(|_| store.put(&mut File::open(&spool_path)?));
+    let _ = fs::remove_file(&spool_path);
+    let blob = written?;
+
+    let mut conn = db.get()?;
+    Export {
+        user_id: user.user_id,
+        job_id: job.job_id,
+        hash: blob.hash,
+        path: blob.path,
+        size_bytes: blob.size_bytes,
+        created_at: manifest.exported_at
+    }.create_new(&mut *conn)?;
+
+    let _ = SSE.push(
+        user.user_id,
+        "update",
+        &SseEvent::Complete
+    );
+
+    Ok(())
+}
+
+/// Loads everything in the given user's archive.
+fn load_export_contents(client: &mut postgres::Client, user: &User) -> Result<ExportContents, Error> {
+    let likes = client.query(
+        "SELECT track_id, liked_at, first_seen, position, removed_at
+        FROM user_likes
+        WHERE user_id = $1
+        ORDER BY position NULLS LAST, removed_at DESC, track_id",
+        &[&user.user_id]
+    )?.iter().map(|r| ExportedLike {
+        track_id: r.get(0),
+        liked_at: r.get(1),
+        first_seen: r.get(2),
+        position: r.get(3),
+        removed_at: r.get(4)
+    }).collect();
+
+    let track_ids = Track::archived_ids_for_user(client, user)?;
+    let tracks: Vec<TrackInfoLong> = client.query(format!("
+        SELECT {}, {}
+        FROM tracks, soundcloudusers
+        WHERE track_id = ANY($1) AND tracks.sc_user_id = soundcloudusers.sc_user_id
+        ORDER BY track_id
+    ", TRACK_INFO_BRIEF_COLUMNS, TRACK_INFO_LONG_COLUMNS).as_str(), &[&track_ids])?
+        .iter()
+        .map(track_info_long)
+        .collect();
+
+    let playlists: Vec<ExportedPlaylist> = client.query(format!("
+        SELECT {}, up.relation
+        FROM user_playlists up, playlists p, soundcloudusers u
+        WHERE up.user_id = $1 AND up.playlist_id = p.playlist_id
+            AND p.sc_user_id = u.sc_user_id
+        ORDER BY p.playlist_id
+    ", PLAYLIST_INFO_LONG_COLUMNS).as_str(), &[&user.user_id])?
+        .iter()
+        .map(|r| {
+            let relation: String = r.get(PLAYLIST_INFO_LONG_LEN);
+
+            ExportedPlaylist {
+                relation: relation.parse().expect("invalid playlist relation in database"),
+                info: playlist_info_long(r)
+            }
+        })
+        .collect();
+
+    let sc_user_ids: Vec<i64> = tracks
+        .iter()
+        .map(|t| t.brief_info.sc_user_id)
+        .chain(playlists.iter().map(|p| p.info.brief_info.sc_user_id))
+        .collect();
+    let artists: Vec<ScUserInfo> = client.query(format!("
+        SELECT {}
+        FROM soundcloudusers
+        WHERE sc_user_id = ANY($1)
+        ORDER BY sc_user_id
+    ", SC_USER_INFO_COLUMNS).as_str(), &[&sc_user_ids])?
+        .iter()
+        .map(sc_user_info)
+        .collect();
+
+    let media_path = |hash: &str| format!("{}/{}", MEDIA_DIR, hash);
+    let mut media: Vec<ExportedMedia> = client.query(
+        "SELECT track_id, hash, size_bytes, content_type
+        FROM track_audio
+        WHERE track_id = ANY($1)
+        ORDER BY track_id",
+        &[&track_ids]
+    )?.iter().map(|r| {
+        let hash: String = r.get(1);
+
+        ExportedMedia {
+            path: media_path(&hash),
+            hash,
+            size_bytes: r.get(2),
+            content_type: r.get(3),
+            kind: ExportedMediaKind::TrackAudio { track_id: r.get(0) }
+        }
+    }).collect();
+
+    let urls: Vec<String> = tracks
+        .iter()
+        .filter_map(|t| t.artwork_url.clone())
+        .chain(artists.iter().filter_map(|a| a.avatar_url.clone()))
+        .collect();
+    media.extend(client.query(
+        "SELECT url, hash, size_bytes, content_type
+        FROM assets
+        WHERE url = ANY($1)
+        ORDER BY url",
+        &[&urls]
+    )?.iter().map(|r| {
+        let hash: String = r.get(1);
+
+        ExportedMedia {
+            path: media_path(&hash),
+            hash,
+            size_bytes: r.get(2),
+            content_type: r.get(3),
+            kind: ExportedMediaKind::Asset { url: r.get(0) }
+        }
+    }));
+
+    Ok(ExportContents {
+        likes,
+        tracks,
+        playlists,
+        artists,
+        media
+    })
+}
+
+/// Adds a file with the given JSON contents to the given bundle.
+fn append_json<W: Write>(
+    bundle: &mut tar::Builder<W>,
+    path: &str,
+    value: &impl serde::Serialize,
+    mtime: u64
+) -> Result<(), Error> {
+    let json = serde_json::to_vec_pretty(value)?;
+    append_file(bundle, path, json.len() as u64, &json[..], mtime)
+}
+
+/// Adds a file of the given length with the contents of the given reader to
+/// the given bundle.
+fn append_file<W: Write>(
+    bundle: &mut tar::Builder<W>,
+    path: &str,
+    len: u64,
+    contents: impl Read,
+    mtime: u64
+) -> Result<(), Error> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(len);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+
+    Ok(bundle.append_data(&mut header, path, contents)?)
+}
+
+/// Everything in an import, ready to be stored
+#[derive(Default)]
+struct ImportContents {
+    artists: HashMap<i64, SoundCloudUser>,
+    tracks: HashMap<i64, Track>,
+    playlists: HashMap<i64, (Playlist, PlaylistRelation)>,
+    likes: Vec<ExportedLike>,
+    media: Vec<ExportedMedia>,
+    /// When the metadata of each track in a bundle was last refreshed, by
+    /// track id (dumps don't say)
+    track_refreshed_at: HashMap<i64, DateTime<Utc>>,
+    /// When the metadata of each playlist in a bundle was last refreshed, by
+    /// playlist id (dumps don't say)
+    playlist_refreshed_at: HashMap<i64, DateTime<Utc>>
+}
+
+impl ImportContents {
+    /// Adds a track from an orange-zest dump, along with the user that
+    /// posted it, returning the track's id.
+    fn add_sc_track(&mut self, track: &ScTrack) -> Result<i64, Error> {
+        let user = required(track.user.as_ref(), "track.user").map_err(invalid_dump)?;
+        let sc_user = SoundCloudUser::try_from(user).map_err(invalid_dump)?;
+        let track = Track::try_from(track).map_err(invalid_dump)?;
+        let track_id = track.track_id;
+
+        self.artists.insert(sc_user.sc_user_id, sc_user);
+        self.tracks.insert(track_id, track);
+
+        Ok(track_id)
+    }
+
+    /// Adds a playlist from an orange-zest dump, along with its tracks and the
+    /// users that posted them.
+    fn add_sc_playlist(&mut self, playlist: &ScPlaylist) -> Result<(), Error> {
+        for track in required(playlist.tracks.as_ref(), "playlist.tracks").map_err(invalid_dump)? {
+            self.add_sc_track(track)?;
+        }
+
+        let user = required(playlist.user.as_ref(), "playlist.user").map_err(invalid_dump)?;
+        let sc_user = SoundCloudUser::try_from(user).map_err(invalid_dump)?;
+        self.artists.insert(sc_user.sc_user_id, sc_user);
+        // The dump doesn't say whose playlists these are
+        let playlist = Playlist::try_from(playlist).map_err(invalid_dump)?;
+        self.playlists.insert(playlist.playlist_id, (playlist, PlaylistRelation::Unknown));
+
+        Ok(())
+    }
+
+    /// Makes sure everything in the import refers to things that are in it
+    /// too, so it can be stored without breaking the archive.
+    fn check(&self) -> Result<(), Error> {
+        for track in self.tracks.values() {
+            if !self.artists.contains_key(&track.sc_user_id) {
+                return Err(Error::InvalidImport(format!("the artist of track {} is missing", track.track_id)));
+            }
+        }
+
+        for (playlist, _) in self.playlists.values() {
+            if !self.artists.contains_key(&playlist.sc_user_id) {
+                return Err(Error::InvalidImport(format!("the artist of playlist {} is missing", playlist.playlist_id)));
+            }
+        }
+
+        for like in self.likes.iter() {
+            if !self.tracks.contains_key(&like.track_id) {
+                return Err(Error::InvalidImport(format!("liked track {} is missing", like.track_id)));
+            }
+        }
+
+        let mut hashes = HashMap::new();
+        for media in self.media.iter() {
+            if !is_blob_hash(&media.hash) || *hashes.entry(&media.path).or_insert(&media.hash) != &media.hash {
+                return Err(Error::InvalidImport(format!("{} has an invalid hash", media.path)));
+            }
+
+            if let ExportedMediaKind::TrackAudio { track_id } = media.kind {
+                if !self.tracks.contains_key(&track_id) {
+                    return Err(Error::InvalidImport(format!("the track of {} is missing", media.path)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Imports the export bundle or orange-zest dump at the given path into the
+/// given user's archive.
+///
+/// Tracks and playlists that are already stored are only updated if the
+/// import's copy was refreshed more recently, so an old bundle can't overwrite
+/// newer metadata, and no stats snapshots are added for them. Artists, likes
+/// and playlists the user already has, and media that is already stored, are
+/// left alone. Media files are put into the blob store first, and then every
+/// row is written in a single transaction, so if anything turns out to be
+/// wrong, nothing is imported (the stored blobs are left for `gc-blobs`). A dry
+/// run goes through all of the same steps, but rolls the transaction back and
+/// doesn't put anything into the blob store.
+///
+/// An "ImportProgress" SSE event is sent after each media file, track and
+/// playlist is imported, and the import stops without storing anything once
+/// `cancelled` has been set.
+pub fn import_archive(
+    db: &DbPool,
+    store: &dyn BlobStore,
+    user: &User,
+    path: &Path,
+    dry_run: bool,
+    cancelled: &AtomicBool
+) -> Result<ImportReport, Error> {
+    let mut magic = [0; 2];
+    let is_bundle = File::open(path)?.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+    let contents = if is_bundle {
+        read_bundle(path)?
+    } else {
+        read_dump(path)?
+    };
+    contents.check()?;
+
+    // Everything is counted before anything is stored, because storing a
+    // track stores its artist too
+    let (mut report, new_media) = {
+        let mut conn = db.get()?;
+
+        let artist_ids: Vec<i64> = contents.artists.keys().cloned().collect();
+        let track_ids: Vec<i64> = contents.tracks.keys().cloned().collect();
+        let playlist_ids: Vec<i64> = contents.playlists.keys().cloned().collect();
+        let mut report = ImportReport {
+            dry_run,
+            tracks: count_stored(&mut *conn, "tracks", "track_id", &track_ids)?,
+            playlists: count_stored(&mut *conn, "playlists", "playlist_id", &playlist_ids)?,
+            artists: count_stored(&mut *conn, "soundcloudusers", "sc_user_id", &artist_ids)?,
+            likes: ImportCounts::default(),
+            media: ImportCounts::default()
+        };
+
+        let audio_track_ids: Vec<i64> = contents.media
+            .iter()
+            .filter_map(|m| match m.kind {
+                ExportedMediaKind::TrackAudio { track_id } => Some(track_id),
+                _ => None
+            })
+            .collect();
+        let stored_audio: HashSet<i64> = conn.query(
+            "SELECT track_id FROM track_audio WHERE track_id = ANY($1)",
+            &[&audio_track_ids]
+        )?.iter().map(|r| r.get(0)).collect();
+
+        let asset_urls: Vec<String> = contents.media
+            .iter()
+            .filter_map(|m| match &m.kind {
+                ExportedMediaKind::Asset { url } => Some(url.clone()),
+                _ => None
+            })
+            .collect();
+        let stored_assets: HashSet<String> = conn.query(
+            "SELECT url FROM assets WHERE url = ANY($1)",
+            &[&asset_urls]
+        )?.iter().map(|r| r.get(0)).collect();
+
+        let new_media: Vec<&ExportedMedia> = contents.media
+            .iter()
+            .filter(|m| match &m.kind {
+                ExportedMediaKind::TrackAudio { track_id } => !stored_audio.contains(track_id),
+                ExportedMediaKind::Asset { url } => !stored_assets.contains(url)
+            })
+            .collect();
+        report.media = ImportCounts {
+            added: new_media.len(),
+            existing: contents.media.len() - new_media.len()
+        };
+
+        (report, new_media)
+    };
+
+    let total = contents.tracks.len() + contents.playlists.len() + new_media.len();
+    let mut processed = 0;
+    let mut progress = || -> Result<(), Error> {
+        check_cancelled(cancelled)?;
+        processed += 1;
+
+        // We don't really care about errors here
+        let _ = SSE.push(
+            user.user_id,
+            "update",
+            &SseEvent::ImportProgress {
+                processed,
+                total
+            }
+        );
+
+        Ok(())
+    };
+
+    // Storing the media can take a while, so it's done before the
+    // transaction is opened
+    let blobs = if new_media.is_empty() {
+        vec![]
+    } else {
+        store_media(store, path, &new_media, dry_run, &mut progress)?
+    };
+
+    let mut conn = db.get()?;
+    let mut transaction = conn.transaction()?;
+
+    // Tracks and playlists refer to their artists, so those go first
+    for artist in contents.artists.values() {
+        artist.import(&mut transaction)?;
+    }
+
+    for track in contents.tracks.values() {
+        track.import(&mut transaction, contents.track_refreshed_at.get(&track.track_id).cloned())?;
+        progress()?;
+    }
+
+    let mut relations = vec![];
+    for (playlist, relation) in contents.playlists.values() {
+        playlist.import(&mut transaction, contents.playlist_refreshed_at.get(&playlist.playlist_id).cloned())?;
+        relations.push((playlist.playlist_id, *relation));
+        progress()?;
+    }
+    user.import_playlists(&mut transaction, &relations)?;
+
+    let added = user.import_likes(&mut transaction, &contents.likes)? as usize;
+    report.likes = ImportCounts {
+        added,
+        existing: contents.likes.len() - added
+    };
+
+    for (media, blob) in blobs {
+        record_media(&mut transaction, media, &blob)?;
+    }
+
+    // Dropping the transaction rolls it back
+    if !dry_run {
+        transaction.commit()?;
+    }
+
+    let _ = SSE.push(
+        user.user_id,
+        "update",
+        &SseEvent::Complete
+    );
+
+    Ok(report)
+}
+
+/// Reads everything but the media out of the export bundle at the given path.
+fn read_bundle(path: &Path) -> Result<ImportContents, Error> {
+    let mut files = HashMap::new();
+    let mut bundle = open_bundle(path)?;
+
+    for entry in bundle.entries().map_err(unreadable_bundle)? {
+        let mut entry = entry.map_err(unreadable_bundle)?;
+        let name = entry.path().map_err(unreadable_bundle)?.to_string_lossy().into_owned();
+
+        if BUNDLE_JSON_FILES.contains(&name.as_str()) {
+            // One byte more than the limit is read, to tell whether there's more
+            let mut json = vec![];
+            (&mut entry).take(MAX_IMPORT_JSON_BYTES + 1).read_to_end(&mut json).map_err(unreadable_bundle)?;
+            if json.len() as u64 > MAX_IMPORT_JSON_BYTES {
+                return Err(Error::InvalidImport(format!(
+                    "{} is larger than {} bytes",
+                    name,
+                    MAX_IMPORT_JSON_BYTES
+                )));
+            }
+            files.insert(name, json);
+        }
+    }
+
+    // Checked first, since a newer bundle might lay out the rest differently
+    let manifest: ExportManifest = parse_bundle_file(&files, "manifest.json")?;
+    if manifest.schema_version > EXPORT_SCHEMA_VERSION {
+        return Err(Error::InvalidImport(format!(
+            "the bundle has schema version {}, but only versions up to {} are supported",
+            manifest.schema_version,
+            EXPORT_SCHEMA_VERSION
+        )));
+    }
+
+    let likes: Vec<ExportedLike> = parse_bundle_file(&files, "likes.json")?;
+    let tracks: Vec<TrackInfoLong> = parse_bundle_file(&files, "tracks.json")?;
+    let playlists: Vec<ExportedPlaylist> = parse_bundle_file(&files, "playlists.json")?;
+    let artists: Vec<ScUserInfo> = parse_bundle_file(&files, "artists.json")?;
+
+    Ok(ImportContents {
+        track_refreshed_at: tracks
+            .iter()
+            .map(|t| (t.brief_info.track_id, t.last_refreshed_at))
+            .collect(),
+        playlist_refreshed_at: playlists
+            .iter()
+            .map(|p| (p.info.brief_info.playlist_id, p.info.last_refreshed_at))
+            .collect(),
+        artists: artists.into_iter().map(|a| (a.sc_user_id, SoundCloudUser {
+            sc_user_id: a.sc_user_id,
+            avatar_url: a.avatar_url,
+            full_name: a.full_name,
+            username: a.username,
+            permalink_url: a.permalink_url
+        })).collect(),
+        tracks: tracks.into_iter().map(|t| (t.brief_info.track_id, Track {
+            track_id: t.brief_info.track_id,
+            sc_user_id: t.brief_info.sc_user_id,
+            length_ms: t.brief_info.length_ms,
+            created_at: t.brief_info.created_at,
+            title: t.brief_info.title,
+            description: t.description,
+            likes_count: t.likes_count,
+            playback_count: t.brief_info.playback_count,
+            artwork_url: t.artwork_url,
+            permalink_url: t.track_permalink_url,
+            download_url: None
+        })).collect(),
+        playlists: playlists.into_iter().map(|p| (p.info.brief_info.playlist_id, (Playlist {
+            playlist_id: p.info.brief_info.playlist_id,
+            sc_user_id: p.info.brief_info.sc_user_id,
+            track_ids: p.info.track_ids,
+            num_tracks: p.info.brief_info.num_tracks,
+            length_ms: p.info.brief_info.length_ms,
+            created_at: p.info.brief_info.created_at,
+            title: p.info.brief_info.title,
+            permalink_url: p.info.playlist_permalink_url,
+            description: p.info.description,
+            likes_count: p.info.likes_count,
+            is_album: p.info.brief_info.is_album
+        }, p.relation))).collect(),
+        likes,
+        media: manifest.media
+    })
+}
+
+/// Reads the orange-zest `Likes` or `Playlists` dump at the given path.
+///
+/// Likes are imported as current likes, most recently liked first, just like
+/// a scrape would store them.
+fn read_dump(path: &Path) -> Result<ImportContents, Error> {
+    if fs::metadata(path)?.len() > MAX_IMPORT_JSON_BYTES {
+        return Err(Error::InvalidImport(format!("the dump is larger than {} bytes", MAX_IMPORT_JSON_BYTES)));
+    }
+
+    let json: serde_json::Value = serde_json::from_reader(BufReader::new(File::open(path)?))
+        .map_err(|e| Error::InvalidImport(format!("the upload is neither a bundle nor JSON: {}", e)))?;
+    let malformed = |e: serde_json::Error| Error::InvalidImport(format!("the dump is malformed: {}", e));
+    let mut contents = ImportContents::default();
+
+    if json.get("collections").is_some() {
+        let likes: Likes = serde_json::from_value(json).map_err(malformed)?;
+        let first_seen = Utc::now();
+        let mut seen = HashSet::new();
+
+        for like in likes.collections.iter() {
+            let track_id = contents.add_sc_track(&like.track)?;
+            if seen.insert(track_id) {
+                contents.likes.push(ExportedLike {
+                    track_id,
+                    liked_at: like.created_at
+                        .as_deref()
+                        .map(|t| parse_sc_timestamp(Some(t)))
+                        .transpose()
+                        .map_err(invalid_dump)?,
+                    first_seen,
+                    position: Some(contents.likes.len() as i32),
+                    removed_at: None
+                });
+            }
+        }
+    } else if json.get("playlists").is_some() {
+        let playlists: Playlists = serde_json::from_value(json).map_err(malformed)?;
+
+        for playlist in playlists.playlists.iter() {
+            contents.add_sc_playlist(playlist)?;
+        }
+    } else {
+        return Err(Error::InvalidImport("the upload is neither a bundle nor a likes or playlists dump".into()));
+    }
+
+    Ok(contents)
+}
+
+/// Puts the given media files from the export bundle at the given path into
+/// the blob store, making sure each of them matches its hash, and returns each
+/// of them along with its blob.
+///
+/// In a dry run the files are only checked. `progress` is called after each
+/// file, and stops the import if it returns an error.
+fn store_media<'a>(
+    store: &dyn BlobStore,
+    path: &Path,
+    media: &[&'a ExportedMedia],
+    dry_run: bool,
+    progress: &mut dyn FnMut() -> Result<(), Error>
+) -> Result<Vec<(&'a ExportedMedia, StoredBlob)>, Error> {
+    let mut wanted: HashMap<&str, Vec<&ExportedMedia>> = HashMap::new();
+    for &m in media {
+        wanted.entry(m.path.as_str()).or_default().push(m);
+    }
+
+    let mut stored = vec![];
+    let mut bundle = open_bundle(path)?;
+    for entry in bundle.entries().map_err(unreadable_bundle)? {
+        let mut entry = entry.map_err(unreadable_bundle)?;
+        let name = entry.path().map_err(unreadable_bundle)?.to_string_lossy().into_owned();
+        let media = match wanted.remove(name.as_str()) {
+            Some(media) => media,
+            None => continue
+        };
+
+        let blob = if dry_run {
+            let (hash, size_bytes) = copy_hashed(&mut entry, &mut io::sink()).map_err(unreadable_bundle)?;
+
+            StoredBlob {
+                path: blob_path(&hash),
+                hash,
+                size_bytes
+            }
+        } else {
+            store.put(&mut entry)?
+        };
+        if blob.hash != media[0].hash {
+            return Err(Error::InvalidImport(format!("{} doesn't match its hash", name)));
+        }
+
+        for m in media {
+            stored.push((m, StoredBlob {
+                hash: blob.hash.clone(),
+                path: blob.path.clone(),
+                size_bytes: blob.size_bytes
+            }));
+            progress()?;
+        }
+    }
+
+    match wanted.keys().next() {
+        Some(name) => Err(Error::InvalidImport(format!("{} is missing from the bundle", name))),
+        None => Ok(stored)
+    }
+}
+
+/// Records that the given imported media file is stored as the given blob.
+fn record_media<C: GenericClient>(client: &mut C, media: &ExportedMedia, blob: &StoredBlob) -> Result<(), Error> {
+    match &media.kind {
+        ExportedMediaKind::TrackAudio { track_id } => TrackAudio {
+            track_id: *track_id,
+            hash: blob.hash.clone(),
+            path: blob.path.clone(),
+            size_bytes: blob.size_bytes,
+            content_type: media.content_type.clone()
+        }.create_new(client),
+        ExportedMediaKind::Asset { url } => Asset {
+            url: url.clone(),
+            hash: blob.hash.clone(),
+            path: blob.path.clone(),
+            size_bytes: blob.size_bytes,
+            content_type: media.content_type.clone()
+        }.create_new(client)
+    }
+}
+
+/// Opens the export bundle at the given path for reading.
+fn open_bundle(path: &Path) -> Result<tar::Archive<GzDecoder<File>>, Error> {
+    Ok(tar::Archive::new(GzDecoder::new(File::open(path)?)))
+}
+
+/// Parses the JSON file with the given name out of the given bundle files.
+fn parse_bundle_file<T: DeserializeOwned>(files: &HashMap<String, Vec<u8>>, name: &str) -> Result<T, Error> {
+    let json = files
+        .get(name)
+        .ok_or_else(|| Error::InvalidImport(format!("{} is missing from the bundle", name)))?;
+
+    serde_json::from_slice(json).map_err(|e| Error::InvalidImport(format!("{} is malformed: {}", name, e)))
+}
+
+/// Counts how many of the given ids are already stored in the given column of
+/// the given table.
+fn count_stored<C: GenericClient>(
+    client: &mut C,
+    table: &str,
+    column: &str,
+    ids: &[i64]
+) -> Result<ImportCounts, Error> {
+    let ids = ids.to_vec();
+    let existing: i64 = client.query_one(
+        format!("SELECT COUNT(*) FROM {} WHERE {} = ANY($1)", table, column).as_str(),
+        &[&ids]
+    )?.get(0);
+
+    Ok(ImportCounts {
+        added: ids.len() - existing as usize,
+        existing: existing as usize
+    })
+}
+
+/// Turns an error from reading an export bundle into an `InvalidImport` error,
+/// since the bundle is what's broken.
+fn unreadable_bundle(e: io::Error) -> Error {
+    Error::InvalidImport(format!("the bundle can't be read: {}", e))
+}
+
+/// Turns an error about bad data in a dump into an `InvalidImport` error.
+fn invalid_dump(e: Error) -> Error {
+    match e {
+        Error::MalformedTimestamp(t) => Error::InvalidImport(format!("malformed timestamp {:?}", t)),
+        Error::MissingField(f) => Error::InvalidImport(format!("{} is missing", f)),
+        e => e
+    }
+}
@@ -1,36 +1,53 @@
 use rocket::{request::{self, FromRequest}, Request, State, Outcome, http::Status};
 use json_structs::*;
-use postgres::Client;
+use postgres::{Client, Config, GenericClient, Row};
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+use postgres::error::SqlState;
+use chrono::{DateTime, Utc};
 use argonautica::{Hasher, Verifier};
 use orange_zest::api::common::{Track as ScTrack, User as ScUser};
 use orange_zest::api::playlists::Playlist as ScPlaylist;
 
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
 use super::*;
 
-pub type DbClient = Arc<Mutex<Client>>;
+pub type DbPool = Pool<PostgresConnectionManager<NoTls>>;
+
+/// How many connections the pool holds if `POSTGRES_POOL_SIZE` isn't set
+const DEFAULT_POOL_SIZE: u32 = 10;
 
-/// Creates a PostgreSQL client based off of environment variables.
-pub fn postgresql_client() -> Result<Client, Error> {
-    let mut client = Client::configure()
+/// Creates a PostgreSQL connection pool based off of environment variables.
+pub fn postgresql_pool() -> Result<DbPool, Error> {
+    let mut config = Config::new();
+    config
         .port(env::var("POSTGRES_PORT").unwrap().parse().unwrap())
         .user(&env::var("POSTGRES_USER").unwrap())
         .dbname(&env::var("POSTGRES_DBNAME").unwrap())
         .host(&env::var("POSTGRES_HOST").unwrap())
-        .password(&env::var("POSTGRES_PASSWORD").unwrap_or("".into()))
-        .connect(NoTls)?;
+        .password(&env::var("POSTGRES_PASSWORD").unwrap_or("".into()));
 
-    create_tables(&mut client)?;
-    Ok(client)
+    db_pool(config)
 }
 
-/// Creates any tables required by the backend if they do not exist already.
-pub fn create_tables(client: &mut Client) -> Result<(), Error> {
-    User::create_table(client)?;
-    SoundCloudUser::create_table(client)?;
-    Track::create_table(client)?;
-    Playlist::create_table(client)?;
+/// Creates a connection pool for the database described by the given config,
+/// bringing the database's schema up to date first.
+///
+/// The pool holds up to `POSTGRES_POOL_SIZE` connections.
+pub fn db_pool(config: Config) -> Result<DbPool, Error> {
+    let size = match env::var("POSTGRES_POOL_SIZE") {
+        Ok(size) => size.parse().expect("POSTGRES_POOL_SIZE must be a positive number"),
+        Err(_) => DEFAULT_POOL_SIZE
+    };
 
-    Ok(())
+    let pool = Pool::builder()
+        .max_size(size)
+        .build(PostgresConnectionManager::new(config, NoTls))?;
+
+    run_migrations(&mut pool.get()?)?;
+    Ok(pool)
 }
 
 /// Request guard to validate request is not coming from an authenticated user.
@@ -73,11 +90,10 @@ pub struct User {
     pub sc_oauth_token: Option<String>,
     /// The client id that corresponds to this user's account on SoundCloud
     pub sc_client_id: Option<String>,
-    /// A vector of ids for tracks that this user has liked on SoundCloud
-    pub liked_track_ids: Vec<i64>,
-    /// A vector of ids for playlists that this user has made or liked on
-    /// SoundCloud
-    pub playlist_ids: Vec<i64>
+    /// How often this user's data is scraped automatically
+    pub scrape_schedule: ScrapeSchedule,
+    /// When the next scheduled scrape for this user is due
+    pub next_scheduled_scrape_at: Option<DateTime<Utc>>
 }
 
 impl From<User> for UserInfo {
@@ -93,39 +109,38 @@ impl<'a, 'r> FromRequest<'a, 'r> for User {
     type Error = Error;
 
     fn from_request(request: &'a Request<'r>) -> request::Outcome<User, Self::Error> {
-        let db = request.guard::<State<DbClient>>().unwrap();
-        let mut client = db.lock().unwrap();
+        let db = request.guard::<State<DbPool>>().unwrap();
 
         let res = request.cookies()
             .get_private("user_id")
             .and_then(|cookie| cookie.value().parse().ok())
-            .map(|id| User::load_id(&mut client, id));
+            .map(|id| User::load_id(&mut *db.get()?, id));
 
         match res {
             Some(Ok(r)) => Outcome::Success(r),
-            Some(Err(err)) => Outcome::Failure((Status::InternalServerError, err)),
+            Some(Err(err)) => Outcome::Failure((err.status(), err)),
             None => Outcome::Forward(())
         }
     }
 }
 
 impl User {
-    /// Creates a table in the given database for storing this struct.
+    /// Builds a user from a row containing all of the table's columns.
     ///
-    /// The table will only be created if it does not already exist.
-    pub fn create_table(client: &mut Client) -> Result<(), Error> {
-        Ok(client.execute(
-            "CREATE TABLE IF NOT EXISTS users (
-                user_id             SERIAL PRIMARY KEY,
-                username            TEXT NOT NULL UNIQUE,
-                hash                TEXT NOT NULL,
-                sc_oauth_token      TEXT,
-                sc_client_id        TEXT,
-                liked_track_ids     BIGINT[] NOT NULL,
-                playlist_ids        BIGINT[] NOT NULL
-            )",
-            &[]
-        ).map(|_| ())?)
+    /// Columns are looked up by name, since columns added by migrations don't
+    /// necessarily come in the same order in every database.
+    fn from_row(row: &Row) -> Self {
+        let scrape_schedule: String = row.get("scrape_schedule");
+
+        Self {
+            user_id: row.get("user_id"),
+            username: row.get("username"),
+            hash: row.get("hash"),
+            sc_oauth_token: row.get("sc_oauth_token"),
+            sc_client_id: row.get("sc_client_id"),
+            scrape_schedule: scrape_schedule.parse().expect("unknown schedule in users table"),
+            next_scheduled_scrape_at: row.get("next_scheduled_scrape_at")
+        }
     }
 
     /// Checks to see if a user with the given username exists and returns true
@@ -158,69 +173,83 @@ impl User {
             .hash()
             .unwrap();
 
-        // Needed to make types work out
-        let empty_vec: Vec<i64> = vec![];
-
         Ok(client.query_one(
-            "INSERT INTO users (
-                hash, username, liked_track_ids, playlist_ids
-            ) VALUES ($1, $2, $3, $4) RETURNING user_id",
-            &[&hash, &rinfo.username, &empty_vec, &empty_vec],
+            "INSERT INTO users (hash, username) VALUES ($1, $2) RETURNING user_id",
+            &[&hash, &rinfo.username],
         )?.get(0))
     }
 
     /// Loads the user specified by the given id from the database
     pub fn load_id(client: &mut Client, id: i32) -> Result<Self, Error> {
-        let row = client.query_one("
-            SELECT
-                user_id,
-                hash,
-                username,
-                sc_oauth_token,
-                sc_client_id,
-                liked_track_ids,
-                playlist_ids
-            FROM users WHERE user_id = $1", &[&id])?;
-
-        Ok(Self {
-            user_id: row.get(0),
-            hash: row.get(1),
-            username: row.get(2),
-            sc_oauth_token: row.get(3),
-            sc_client_id: row.get(4),
-            liked_track_ids: row.get(5),
-            playlist_ids: row.get(6)
-        })
+        let row = client.query_one("SELECT * FROM users WHERE user_id = $1", &[&id])?;
+        Ok(Self::from_row(&row))
     }
 
     /// Loads the user specified by the given username from the database
     pub fn load_username(client: &mut Client, username: &str) -> Result<Self, Error> {
-        let maybe_row = client.query_opt("
-            SELECT
-                user_id,
-                hash,
-                username,
-                sc_oauth_token,
-                sc_client_id,
-                liked_track_ids,
-                playlist_ids
-            FROM users WHERE username = $1", &[&username])?;
+        let maybe_row = client.query_opt("SELECT * FROM users WHERE username = $1", &[&username])?;
 
         if let Some(row) = maybe_row {
-            Ok(Self {
-                user_id: row.get(0),
-                hash: row.get(1),
-                username: row.get(2),
-                sc_oauth_token: row.get(3),
-                sc_client_id: row.get(4),
-                liked_track_ids: row.get(5),
-                playlist_ids: row.get(6)
-            })
+            Ok(Self::from_row(&row))
         } else {
             Err(Error::LoginFailed)
         }
     }
 
+    /// Loads every user whose next scheduled scrape is due.
+    ///
+    /// Users that haven't stored their SoundCloud credentials are left out,
+    /// since scraping for them would only fail. They stay due, so they are
+    /// picked up once they have stored them.
+    pub fn load_due_for_scheduled_scrape(client: &mut Client) -> Result<Vec<Self>, Error> {
+        Ok(client.query(
+            "SELECT * FROM users
+            WHERE scrape_schedule <> 'off' AND next_scheduled_scrape_at <= now()
+                AND sc_oauth_token IS NOT NULL AND sc_client_id IS NOT NULL
+            ORDER BY next_scheduled_scrape_at",
+            &[]
+        )?.iter().map(Self::from_row).collect())
+    }
+
+    /// Changes how often this user's data is scraped automatically.
+    ///
+    /// Turning the schedule on makes the first scheduled scrape due right away.
+    pub fn set_scrape_schedule(
+        &mut self,
+        client: &mut Client,
+        schedule: ScrapeSchedule
+    ) -> Result<(), Error> {
+        let next_run_at = match (self.scrape_schedule, schedule) {
+            (_, ScrapeSchedule::Off) => None,
+            (ScrapeSchedule::Off, _) => Some(Utc::now()),
+            (_, _) => self.next_scheduled_scrape_at
+        };
+
+        client.execute(
+            "UPDATE users SET scrape_schedule = $1, next_scheduled_scrape_at = $2
+            WHERE user_id = $3",
+            &[&schedule.as_str(), &next_run_at, &self.user_id]
+        )?;
+
+        self.scrape_schedule = schedule;
+        self.next_scheduled_scrape_at = next_run_at;
+        Ok(())
+    }
+
+    /// Pushes this user's next scheduled scrape back by one interval of their
+    /// schedule, counting from now.
+    pub fn schedule_next_scrape(&mut self, client: &mut Client) -> Result<(), Error> {
+        let next_run_at = self.scrape_schedule.interval().map(|i| Utc::now() + i);
+
+        client.execute(
+            "UPDATE users SET next_scheduled_scrape_at = $1 WHERE user_id = $2",
+            &[&next_run_at, &self.user_id]
+        )?;
+
+        self.next_scheduled_scrape_at = next_run_at;
+        Ok(())
+    }
+
     /// Stores the given `AuthCredentials` in the databse for this user.
     pub fn store_sc_credentials(
         &self,
@@ -233,27 +262,184 @@ impl User {
         ).map(|_| ())?)
     }
 
-    /// Set this user's liked_track_ids to the values produced by the given iterator.
-    pub fn update_liked_track_ids<I: IntoIterator<Item = i64>>(
+    /// Loads the ids of the tracks this user currently likes, most recently
+    /// liked first.
+    pub fn liked_track_ids<C: GenericClient>(&self, client: &mut C) -> Result<Vec<i64>, Error> {
+        Ok(client.query(
+            "SELECT track_id FROM user_likes
+            WHERE user_id = $1 AND removed_at IS NULL
+            ORDER BY position",
+            &[&self.user_id]
+        )?.iter().map(|r| r.get(0)).collect())
+    }
+
+    /// Records that the given tracks are this user's most recent likes, most
+    /// recently liked first, along with when each of them was liked (if that's
+    /// known).
+    ///
+    /// The tracks take the first positions in the user's likes, and any other
+    /// current likes move down behind them in the order they were in. Tracks
+    /// that had been unliked before are marked as liked again. A like time
+    /// that was recorded before is kept if no time is given now.
+    pub fn record_likes<C: GenericClient>(
         &self,
-        client: &mut Client,
-        ids: I
+        client: &mut C,
+        likes: &[(i64, Option<DateTime<Utc>>)]
     ) -> Result<(), Error> {
+        let mut seen = HashSet::new();
+        let (track_ids, liked_at): (Vec<i64>, Vec<Option<DateTime<Utc>>>) = likes
+            .iter()
+            .cloned()
+            .filter(|(id, _)| seen.insert(*id))
+            .unzip();
+
+        client.execute(
+            "UPDATE user_likes l SET position = $3 + others.rank - 1
+            FROM (
+                SELECT track_id, row_number() OVER (ORDER BY position) AS rank
+                FROM user_likes
+                WHERE user_id = $1 AND removed_at IS NULL AND NOT (track_id = ANY($2))
+            ) others
+            WHERE l.user_id = $1 AND l.track_id = others.track_id",
+            &[&self.user_id, &track_ids, &(track_ids.len() as i64)]
+        )?;
+
         Ok(client.execute(
-            "UPDATE users SET liked_track_ids = $1 WHERE user_id = $2",
-            &[&ids.into_iter().collect::<Vec<i64>>(), &self.user_id]
+            "INSERT INTO user_likes (user_id, track_id, liked_at, position)
+            SELECT $1, t.track_id, t.liked_at, (t.ord - 1)::INTEGER
+            FROM unnest($2::BIGINT[], $3::TIMESTAMPTZ[]) WITH ORDINALITY AS t(track_id, liked_at, ord)
+            ON CONFLICT (user_id, track_id) DO UPDATE
+            SET liked_at = COALESCE(EXCLUDED.liked_at, user_likes.liked_at),
+                position = EXCLUDED.position, removed_at = NULL",
+            &[&self.user_id, &track_ids, &liked_at]
         ).map(|_| ())?)
     }
 
-    /// Set this user's playlist_ids to the values produced by the given iterator.
-    pub fn update_playlist_ids<I: IntoIterator<Item = i64>>(
+    /// Records that the given tracks are no longer liked by this user.
+    pub fn record_removed_likes<C: GenericClient>(
         &self,
-        client: &mut Client,
-        ids: I
+        client: &mut C,
+        track_ids: &[i64]
+    ) -> Result<(), Error> {
+        Ok(client.execute(
+            "UPDATE user_likes SET removed_at = now(), position = NULL
+            WHERE user_id = $1 AND track_id = ANY($2) AND removed_at IS NULL",
+            &[&self.user_id, &track_ids]
+        ).map(|_| ())?)
+    }
+
+    /// Adds the given imported likes to this user's likes and like history,
+    /// returning how many of them weren't there already.
+    ///
+    /// Likes the user already has are left alone. Imported current likes that
+    /// are new go behind the user's current likes, in the order they are given
+    /// in.
+    pub fn import_likes<C: GenericClient>(
+        &self,
+        client: &mut C,
+        likes: &[ExportedLike]
+    ) -> Result<u64, Error> {
+        let track_ids: Vec<i64> = likes.iter().map(|l| l.track_id).collect();
+        let liked_at: Vec<Option<DateTime<Utc>>> = likes.iter().map(|l| l.liked_at).collect();
+        let first_seen: Vec<DateTime<Utc>> = likes.iter().map(|l| l.first_seen).collect();
+        let removed_at: Vec<Option<DateTime<Utc>>> = likes.iter().map(|l| l.removed_at).collect();
+
+        // Positions can have gaps, only their order matters
+        Ok(client.execute(
+            "INSERT INTO user_likes (user_id, track_id, liked_at, first_seen, position, removed_at)
+            SELECT $1, l.track_id, l.liked_at, l.first_seen,
+                CASE WHEN l.removed_at IS NULL THEN (
+                    SELECT COALESCE(MAX(position) + 1, 0) FROM user_likes WHERE user_id = $1
+                ) + (l.ord - 1)::INTEGER END,
+                l.removed_at
+            FROM unnest($2::BIGINT[], $3::TIMESTAMPTZ[], $4::TIMESTAMPTZ[], $5::TIMESTAMPTZ[])
+                WITH ORDINALITY AS l(track_id, liked_at, first_seen, removed_at, ord)
+            ON CONFLICT (user_id, track_id) DO NOTHING",
+            &[&self.user_id, &track_ids, &liked_at, &first_seen, &removed_at]
+        )?)
+    }
+
+    /// Loads the ids of the tracks this user currently likes whose audio hasn't
+    /// been archived yet, most recently liked first.
+    pub fn liked_track_ids_without_audio(&self, client: &mut Client) -> Result<Vec<i64>, Error> {
+        Ok(client.query(
+            "SELECT l.track_id FROM user_likes l
+            WHERE l.user_id = $1 AND l.removed_at IS NULL
+                AND NOT EXISTS (SELECT 1 FROM track_audio a WHERE a.track_id = l.track_id)
+            ORDER BY l.position",
+            &[&self.user_id]
+        )?.iter().map(|r| r.get(0)).collect())
+    }
+
+    /// Forgets this user's current likes.
+    ///
+    /// Likes that were already removed are kept in the user's like history.
+    pub fn clear_likes(&self, client: &mut Client) -> Result<(), Error> {
+        Ok(client.execute(
+            "DELETE FROM user_likes WHERE user_id = $1 AND removed_at IS NULL",
+            &[&self.user_id]
+        ).map(|_| ())?)
+    }
+
+    /// Loads the ids of the playlists this user has made or liked.
+    pub fn playlist_ids<C: GenericClient>(&self, client: &mut C) -> Result<Vec<i64>, Error> {
+        Ok(client.query(
+            "SELECT playlist_id FROM user_playlists WHERE user_id = $1",
+            &[&self.user_id]
+        )?.iter().map(|r| r.get(0)).collect())
+    }
+
+    /// Records that the given playlists relate to this user in the given ways.
+    ///
+    /// The user's other playlists are left alone.
+    pub fn record_playlists<C: GenericClient>(
+        &self,
+        client: &mut C,
+        playlists: &[(i64, PlaylistRelation)]
     ) -> Result<(), Error> {
+        let relations: HashMap<i64, PlaylistRelation> = playlists.iter().cloned().collect();
+        let (playlist_ids, relations): (Vec<i64>, Vec<&str>) = relations
+            .iter()
+            .map(|(id, relation)| (*id, relation.as_str()))
+            .unzip();
+
+        Ok(client.execute(
+            "INSERT INTO user_playlists (user_id, playlist_id, relation)
+            SELECT $1, p.playlist_id, p.relation
+            FROM unnest($2::BIGINT[], $3::TEXT[]) AS p(playlist_id, relation)
+            ON CONFLICT (user_id, playlist_id) DO UPDATE SET relation = EXCLUDED.relation",
+            &[&self.user_id, &playlist_ids, &relations]
+        ).map(|_| ())?)
+    }
+
+    /// Adds the given imported playlists to the playlists this user has made or
+    /// liked, returning how many of them weren't there already.
+    ///
+    /// Playlists the user already has keep the relation they have.
+    pub fn import_playlists<C: GenericClient>(
+        &self,
+        client: &mut C,
+        playlists: &[(i64, PlaylistRelation)]
+    ) -> Result<u64, Error> {
+        let (playlist_ids, relations): (Vec<i64>, Vec<&str>) = playlists
+            .iter()
+            .map(|(id, relation)| (*id, relation.as_str()))
+            .unzip();
+
+        Ok(client.execute(
+            "INSERT INTO user_playlists (user_id, playlist_id, relation)
+            SELECT $1, p.playlist_id, p.relation
+            FROM unnest($2::BIGINT[], $3::TEXT[]) AS p(playlist_id, relation)
+            ON CONFLICT (user_id, playlist_id) DO NOTHING",
+            &[&self.user_id, &playlist_ids, &relations]
+        )?)
+    }
+
+    /// Forgets which playlists this user has made or liked.
+    pub fn clear_playlists(&self, client: &mut Client) -> Result<(), Error> {
         Ok(client.execute(
-            "UPDATE users SET playlist_ids = $1 WHERE user_id = $2",
-            &[&ids.into_iter().collect::<Vec<i64>>(), &self.user_id]
+            "DELETE FROM user_playlists WHERE user_id = $1",
+            &[&self.user_id]
         ).map(|_| ())?)
     }
 
@@ -285,8 +471,8 @@ pub struct Track {
     pub sc_user_id: i64,
     /// The length of the track in milliseconds
     pub length_ms: i64,
-    /// When the track was uploaded to SoundCloud as a date-time string
-    pub created_at: String,
+    /// When the track was uploaded to SoundCloud
+    pub created_at: DateTime<Utc>,
     /// The name of the track
     pub title: String,
     /// A description of the track written by the user who posted it
@@ -305,54 +491,113 @@ pub struct Track {
     pub download_url: Option<String>
 }
 
-impl From<&ScTrack> for Track {
-    fn from(track: &ScTrack) -> Self {
-        Track {
-            track_id: track.id.unwrap(),
-            sc_user_id: track.user_id.unwrap(),
-            length_ms: track.duration.unwrap(),
-            created_at: track.created_at.clone().unwrap(),
+impl TryFrom<&ScTrack> for Track {
+    type Error = Error;
+
+    /// Errors with `Error::MalformedTimestamp` if the track's creation date
+    /// can't be parsed, and with `Error::MissingField` if the track lacks a
+    /// field we need.
+    fn try_from(track: &ScTrack) -> Result<Self, Self::Error> {
+        Ok(Track {
+            track_id: required(track.id, "track.id")?,
+            sc_user_id: required(track.user_id, "track.user_id")?,
+            length_ms: required(track.duration, "track.duration")?,
+            created_at: parse_sc_timestamp(track.created_at.as_deref())?,
             title: track.title.clone().unwrap_or("".into()),
             description: track.description.clone().unwrap_or("".into()),
             likes_count: track.likes_count.clone().unwrap_or(0),
             playback_count: track.playback_count.unwrap_or(0),
             artwork_url: track.artwork_url.clone(),
-            permalink_url: track.permalink_url.clone().unwrap(),
+            permalink_url: required(track.permalink_url.clone(), "track.permalink_url")?,
             download_url: None
-        }
+        })
     }
 }
 
 impl Track {
-    /// Creates a table in the given database for storing this struct.
+    /// Creates a new track in the database based on an instance of the struct
+    /// and a reference to the user that owns the track.
     ///
-    /// The table will only be created if it does not already exist.
-    pub fn create_table(client: &mut Client) -> Result<(), Error> {
+    /// If the track is already stored, its metadata is updated to match the
+    /// struct instead (except for a known download URL, which is kept if the
+    /// struct doesn't have one). The same goes for the user.
+    ///
+    /// Either way, the track's current play and like counts are added to its
+    /// stats history, unless they were already added in the same transaction
+    /// (as happens to a liked track that's also in a playlist).
+    pub fn create_new<C: GenericClient>(&self, client: &mut C, user: &SoundCloudUser) -> Result<(), Error> {
+        user.create_new(client)?;
+        client.execute(
+            "INSERT INTO tracks (
+                track_id, sc_user_id, length_ms, created_at, title, description,
+                likes_count, playback_count, artwork_url, permalink_url, download_url
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (track_id) DO UPDATE SET
+                sc_user_id = EXCLUDED.sc_user_id,
+                length_ms = EXCLUDED.length_ms,
+                title = EXCLUDED.title,
+                description = EXCLUDED.description,
+                likes_count = EXCLUDED.likes_count,
+                playback_count = EXCLUDED.playback_count,
+                artwork_url = EXCLUDED.artwork_url,
+                permalink_url = EXCLUDED.permalink_url,
+                download_url = COALESCE(EXCLUDED.download_url, tracks.download_url),
+                last_refreshed_at = now()",
+            &[
+                &self.track_id,
+                &self.sc_user_id,
+                &self.length_ms,
+                &self.created_at,
+                &self.title,
+                &self.description,
+                &self.likes_count,
+                &self.playback_count,
+                &self.artwork_url,
+                &self.permalink_url,
+                &self.download_url
+            ],
+        )?;
+
         Ok(client.execute(
-            "CREATE TABLE IF NOT EXISTS tracks (
-                track_id        BIGINT PRIMARY KEY,
-                sc_user_id      BIGINT NOT NULL references soundcloudusers(sc_user_id),
-                length_ms       BIGINT NOT NULL,
-                created_at      TEXT NOT NULL,
-                title           TEXT NOT NULL,
-                description     TEXT NOT NULL,
-                likes_count     BIGINT NOT NULL,
-                playback_count  BIGINT NOT NULL,
-                artwork_url     TEXT,
-                permalink_url   TEXT NOT NULL,
-                download_url    TEXT
-            )",
-            &[]
+            "INSERT INTO track_stats_snapshots (track_id, playback_count, likes_count)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING",
+            &[&self.track_id, &self.playback_count, &self.likes_count]
         ).map(|_| ())?)
     }
 
-    /// Creates a new track in the database based on an instance of the struct
-    /// and a reference to the user that owns the track.
-    pub fn create_new(&self, client: &mut Client, user: &SoundCloudUser) -> Result<(), Error> {
-        user.create_new(client)?;
+    /// Stores a track that was imported from an export bundle or dump, whose
+    /// metadata was last refreshed at the given time, if that's known.
+    ///
+    /// Unlike `create_new`, an already stored track is only updated if the
+    /// imported copy was refreshed more recently than the stored one, no stats
+    /// snapshot is added (the imported counts aren't current), and the track's
+    /// user isn't stored. Returns whether anything was written.
+    pub fn import<C: GenericClient>(
+        &self,
+        client: &mut C,
+        last_refreshed_at: Option<DateTime<Utc>>
+    ) -> Result<bool, Error> {
         Ok(client.execute(
-            "INSERT INTO tracks VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
-            ON CONFLICT DO NOTHING",
+            "INSERT INTO tracks (
+                track_id, sc_user_id, length_ms, created_at, title, description,
+                likes_count, playback_count, artwork_url, permalink_url, download_url,
+                last_refreshed_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, COALESCE($12, now()))
+            ON CONFLICT (track_id) DO UPDATE SET
+                sc_user_id = EXCLUDED.sc_user_id,
+                length_ms = EXCLUDED.length_ms,
+                title = EXCLUDED.title,
+                description = EXCLUDED.description,
+                likes_count = EXCLUDED.likes_count,
+                playback_count = EXCLUDED.playback_count,
+                artwork_url = EXCLUDED.artwork_url,
+                permalink_url = EXCLUDED.permalink_url,
+                download_url = COALESCE(EXCLUDED.download_url, tracks.download_url),
+                last_refreshed_at = EXCLUDED.last_refreshed_at
+            WHERE tracks.last_refreshed_at < $12",
             &[
                 &self.track_id,
                 &self.sc_user_id,
@@ -364,11 +609,43 @@ impl Track {
                 &self.playback_count,
                 &self.artwork_url,
                 &self.permalink_url,
-                &self.download_url
+                &self.download_url,
+                &last_refreshed_at
             ],
+        )? > 0)
+    }
+
+    /// Records whether the track with the given id was found on SoundCloud
+    /// just now.
+    pub fn set_availability(
+        client: &mut Client,
+        track_id: i64,
+        availability: TrackAvailability
+    ) -> Result<(), Error> {
+        Ok(client.execute(
+            "UPDATE tracks SET availability = $1, last_verified_at = now() WHERE track_id = $2",
+            &[&availability.as_str(), &track_id]
         ).map(|_| ())?)
     }
 
+    /// Loads the ids of every track in the given user's archive.
+    ///
+    /// That's the tracks the user likes or used to like, and the tracks in the
+    /// user's playlists.
+    pub fn archived_ids_for_user(client: &mut Client, user: &User) -> Result<Vec<i64>, Error> {
+        Ok(client.query(
+            "SELECT track_id FROM tracks
+            WHERE track_id IN (SELECT track_id FROM user_likes WHERE user_id = $1)
+                OR track_id IN (
+                    SELECT pt.track_id
+                    FROM playlist_tracks pt, user_playlists up
+                    WHERE up.user_id = $1 AND pt.playlist_id = up.playlist_id
+                )
+            ORDER BY track_id",
+            &[&user.user_id]
+        )?.iter().map(|r| r.get(0)).collect())
+    }
+
     /// Loads the track specified by the given id from the database
     pub fn load_id(client: &mut Client, track_id: i64) -> Result<Self, Error> {
         let row = client.query_one("
@@ -405,6 +682,179 @@ impl Track {
     }
 }
 
+/// The archived audio of a track
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackAudio {
+    /// The track the audio belongs to
+    pub track_id: i64,
+    /// The hash of the audio in the blob store
+    pub hash: String,
+    /// Where the audio is stored, relative to the root of the blob store
+    pub path: String,
+    /// The size of the audio in bytes
+    pub size_bytes: i64,
+    /// The MIME type of the audio
+    pub content_type: String
+}
+
+impl TrackAudio {
+    /// Records that the audio of a track has been archived.
+    ///
+    /// If audio was already recorded for the track, it is replaced.
+    pub fn create_new<C: GenericClient>(&self, client: &mut C) -> Result<(), Error> {
+        Ok(client.execute(
+            "INSERT INTO track_audio (track_id, hash, path, size_bytes, content_type)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (track_id) DO UPDATE SET
+                hash = EXCLUDED.hash,
+                path = EXCLUDED.path,
+                size_bytes = EXCLUDED.size_bytes,
+                content_type = EXCLUDED.content_type,
+                archived_at = now()",
+            &[&self.track_id, &self.hash, &self.path, &self.size_bytes, &self.content_type]
+        ).map(|_| ())?)
+    }
+
+    /// Loads the archived audio of the track with the given id, if there is any
+    pub fn load_for_track(client: &mut Client, track_id: i64) -> Result<Option<Self>, Error> {
+        let rows = client.query(
+            "SELECT track_id, hash, path, size_bytes, content_type
+            FROM track_audio
+            WHERE track_id = $1",
+            &[&track_id]
+        )?;
+
+        Ok(rows.first().map(|r| Self {
+            track_id: r.get(0),
+            hash: r.get(1),
+            path: r.get(2),
+            size_bytes: r.get(3),
+            content_type: r.get(4)
+        }))
+    }
+}
+
+/// An image from SoundCloud's CDN that was mirrored into the blob store
+#[derive(Debug, Clone, PartialEq)]
+pub struct Asset {
+    /// Where the image was downloaded from
+    pub url: String,
+    /// The hash of the image in the blob store
+    pub hash: String,
+    /// Where the image is stored, relative to the root of the blob store
+    pub path: String,
+    /// The size of the image in bytes
+    pub size_bytes: i64,
+    /// The MIME type of the image
+    pub content_type: String
+}
+
+impl Asset {
+    /// Records that the image at a URL has been mirrored.
+    ///
+    /// If the URL was already mirrored, it is pointed at the new copy.
+    pub fn create_new<C: GenericClient>(&self, client: &mut C) -> Result<(), Error> {
+        Ok(client.execute(
+            "INSERT INTO assets (url, hash, path, size_bytes, content_type)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (url) DO UPDATE SET
+                hash = EXCLUDED.hash,
+                path = EXCLUDED.path,
+                size_bytes = EXCLUDED.size_bytes,
+                content_type = EXCLUDED.content_type,
+                mirrored_at = now()",
+            &[&self.url, &self.hash, &self.path, &self.size_bytes, &self.content_type]
+        ).map(|_| ())?)
+    }
+
+    /// Loads a mirrored image with the given hash, if there is one
+    ///
+    /// Many URLs can point at the same image; any one of them is returned.
+    pub fn load_hash(client: &mut Client, hash: &str) -> Result<Option<Self>, Error> {
+        let rows = client.query(
+            "SELECT url, hash, path, size_bytes, content_type
+            FROM assets
+            WHERE hash = $1
+            LIMIT 1",
+            &[&hash]
+        )?;
+
+        Ok(rows.first().map(|r| Self {
+            url: r.get(0),
+            hash: r.get(1),
+            path: r.get(2),
+            size_bytes: r.get(3),
+            content_type: r.get(4)
+        }))
+    }
+
+    /// Picks out the URLs that haven't been mirrored yet from the given ones,
+    /// without duplicates.
+    pub fn unmirrored_urls(client: &mut Client, urls: &[String]) -> Result<Vec<String>, Error> {
+        Ok(client.query(
+            "SELECT DISTINCT u.url
+            FROM unnest($1::TEXT[]) AS u(url)
+            WHERE NOT EXISTS (SELECT 1 FROM assets a WHERE a.url = u.url)",
+            &[&urls]
+        )?.iter().map(|r| r.get(0)).collect())
+    }
+}
+
+/// The latest export bundle of a user's archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export {
+    /// The user whose archive is in the bundle
+    pub user_id: i32,
+    /// The job that made the bundle
+    pub job_id: i32,
+    /// The hash of the bundle in the blob store
+    pub hash: String,
+    /// Where the bundle is stored, relative to the root of the blob store
+    pub path: String,
+    /// The size of the bundle in bytes
+    pub size_bytes: i64,
+    /// When the bundle was made
+    pub created_at: DateTime<Utc>
+}
+
+impl Export {
+    /// Records that a bundle of a user's archive has been made.
+    ///
+    /// If there was a bundle for the user already, it is replaced.
+    pub fn create_new<C: GenericClient>(&self, client: &mut C) -> Result<(), Error> {
+        Ok(client.execute(
+            "INSERT INTO exports (user_id, job_id, hash, path, size_bytes, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (user_id) DO UPDATE SET
+                job_id = EXCLUDED.job_id,
+                hash = EXCLUDED.hash,
+                path = EXCLUDED.path,
+                size_bytes = EXCLUDED.size_bytes,
+                created_at = EXCLUDED.created_at",
+            &[&self.user_id, &self.job_id, &self.hash, &self.path, &self.size_bytes, &self.created_at]
+        ).map(|_| ())?)
+    }
+
+    /// Loads the latest bundle of the given user's archive, if there is one
+    pub fn load_for_user(client: &mut Client, user_id: i32) -> Result<Option<Self>, Error> {
+        let rows = client.query(
+            "SELECT user_id, job_id, hash, path, size_bytes, created_at
+            FROM exports
+            WHERE user_id = $1",
+            &[&user_id]
+        )?;
+
+        Ok(rows.first().map(|r| Self {
+            user_id: r.get(0),
+            job_id: r.get(1),
+            hash: r.get(2),
+            path: r.get(3),
+            size_bytes: r.get(4),
+            created_at: r.get(5)
+        }))
+    }
+}
+
 /// Representation of a SoundCloud user in the database
 #[derive(Debug, PartialEq, Clone)]
 pub struct SoundCloudUser {
@@ -420,41 +870,57 @@ pub struct SoundCloudUser {
     pub permalink_url: String
 }
 
-impl From<&ScUser> for SoundCloudUser {
-    fn from(u: &ScUser) -> Self {
-        Self {
-            sc_user_id: u.id.unwrap(),
+impl TryFrom<&ScUser> for SoundCloudUser {
+    type Error = Error;
+
+    /// Errors with `Error::MissingField` if the user lacks a field we need.
+    fn try_from(u: &ScUser) -> Result<Self, Self::Error> {
+        Ok(Self {
+            sc_user_id: required(u.id, "user.id")?,
             avatar_url: u.avatar_url.clone(),
             full_name: u.full_name.clone().unwrap_or("".into()),
-            username: u.username.clone().unwrap(),
-            permalink_url: u.permalink_url.clone().unwrap()
-        }
+            username: required(u.username.clone(), "user.username")?,
+            permalink_url: required(u.permalink_url.clone(), "user.permalink_url")?
+        })
     }
 }
 
 impl SoundCloudUser {
-    /// Creates a table in the given database for storing this struct.
+    /// Creates a new SoundCloud user in the database based on an instance of
+    /// the struct.
     ///
-    /// The table will only be created if it does not already exist.
-    pub fn create_table(client: &mut Client) -> Result<(), Error> {
+    /// If the user is already stored, their profile is updated to match the
+    /// struct instead.
+    pub fn create_new<C: GenericClient>(&self, client: &mut C) -> Result<(), Error> {
         Ok(client.execute(
-            "CREATE TABLE IF NOT EXISTS soundcloudusers (
-                sc_user_id      BIGINT PRIMARY KEY,
-                avatar_url      TEXT,
-                full_name       TEXT NOT NULL,
-                username        TEXT NOT NULL,
-                permalink_url   TEXT NOT NULL
-            )",
-            &[]
+            "INSERT INTO soundcloudusers (sc_user_id, avatar_url, full_name, username, permalink_url)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (sc_user_id) DO UPDATE SET
+                avatar_url = EXCLUDED.avatar_url,
+                full_name = EXCLUDED.full_name,
+                username = EXCLUDED.username,
+                permalink_url = EXCLUDED.permalink_url,
+                last_refreshed_at = now()",
+            &[
+                &self.sc_user_id,
+                &self.avatar_url,
+                &self.full_name,
+                &self.username,
+                &self.permalink_url
+            ],
         ).map(|_| ())?)
     }
 
-    /// Creates a new SoundCloud user in the database based on an instance of
-    /// the struct.
-    pub fn create_new(&self, client: &mut Client) -> Result<(), Error> {
+    /// Stores a SoundCloud user that was imported from an export bundle or
+    /// dump.
+    ///
+    /// Imports don't say how current a user's profile is, so unlike with
+    /// `create_new`, an already stored user is left alone.
+    pub fn import<C: GenericClient>(&self, client: &mut C) -> Result<(), Error> {
         Ok(client.execute(
-            "INSERT INTO soundcloudusers VALUES ($1, $2, $3, $4, $5)
-             ON CONFLICT DO NOTHING",
+            "INSERT INTO soundcloudusers (sc_user_id, avatar_url, full_name, username, permalink_url)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (sc_user_id) DO NOTHING",
             &[
                 &self.sc_user_id,
                 &self.avatar_url,
@@ -496,14 +962,14 @@ pub struct Playlist {
     pub playlist_id: i64,
     /// The id of the soundcloud user that created this playlist
     pub sc_user_id: i64,
-    /// IDs of tracks that are a part of this playlist
+    /// IDs of tracks that are a part of this playlist, in order
     pub track_ids: Vec<i64>,
     /// The number of tracks in the playlist
     pub num_tracks: i64,
     /// The total length of all tracks in the playlist combined in milliseconds
     pub length_ms: i64,
-    /// When the playlist was created on SoundCloud as a date-time string
-    pub created_at: String,
+    /// When the playlist was created on SoundCloud
+    pub created_at: DateTime<Utc>,
     /// The name of the playlist
     pub title: String,
     /// A URL to the playlist on SoundCloud
@@ -516,67 +982,128 @@ pub struct Playlist {
     pub is_album: bool
 }
 
-impl From<&ScPlaylist> for Playlist {
-    fn from(playlist: &ScPlaylist) -> Self {
-        Self {
-            playlist_id: playlist.id.unwrap(),
-            sc_user_id: playlist.user_id.unwrap(),
-            track_ids: playlist.tracks.as_ref().unwrap().iter().map(|t| t.id.unwrap()).collect(),
-            num_tracks: playlist.tracks.as_ref().unwrap().len() as i64,
+impl TryFrom<&ScPlaylist> for Playlist {
+    type Error = Error;
+
+    /// Errors with `Error::MalformedTimestamp` if the playlist's creation date
+    /// can't be parsed, and with `Error::MissingField` if the playlist or one
+    /// of its tracks lacks a field we need.
+    fn try_from(playlist: &ScPlaylist) -> Result<Self, Self::Error> {
+        let tracks = required(playlist.tracks.as_ref(), "playlist.tracks")?;
+
+        Ok(Self {
+            playlist_id: required(playlist.id, "playlist.id")?,
+            sc_user_id: required(playlist.user_id, "playlist.user_id")?,
+            track_ids: tracks
+                .iter()
+                .map(|t| required(t.id, "track.id"))
+                .collect::<Result<_, _>>()?,
+            num_tracks: tracks.len() as i64,
             length_ms: playlist.duration.unwrap_or(0),
-            created_at: playlist.created_at.clone().unwrap(),
+            created_at: parse_sc_timestamp(playlist.created_at.as_deref())?,
             title: playlist.title.clone().unwrap_or("".into()),
-            permalink_url: playlist.permalink_url.clone().unwrap(),
+            permalink_url: required(playlist.permalink_url.clone(), "playlist.permalink_url")?,
             description: playlist.description.clone().unwrap_or("".into()),
             likes_count: playlist.likes_count.unwrap_or(0),
             is_album: playlist.is_album.unwrap_or(false)
-        }
+        })
     }
 }
 
 impl Playlist {
-    /// Creates a table in the given database for storing this struct.
+    /// Creates a new playlist in the database based on an instance of the struct
+    /// and a reference to the user that made the playlist.
     ///
-    /// The table will only be created if it does not already exist.
-    pub fn create_table(client: &mut Client) -> Result<(), Error> {
+    /// If the playlist is already stored, its metadata and track list are
+    /// updated to match the struct instead. The same goes for the user. The
+    /// playlist's tracks aren't stored by this.
+    ///
+    /// Either way, the playlist's current like and track counts are added to
+    /// its stats history, unless they were already added in the same
+    /// transaction.
+    pub fn create_new<C: GenericClient>(&self, client: &mut C, user: &SoundCloudUser) -> Result<(), Error> {
+        user.create_new(client)?;
+
+        client.execute(
+            "INSERT INTO playlists (
+                playlist_id, sc_user_id, num_tracks, length_ms, created_at,
+                title, permalink_url, description, likes_count, is_album
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT (playlist_id) DO UPDATE SET
+                sc_user_id = EXCLUDED.sc_user_id,
+                num_tracks = EXCLUDED.num_tracks,
+                length_ms = EXCLUDED.length_ms,
+                title = EXCLUDED.title,
+                permalink_url = EXCLUDED.permalink_url,
+                description = EXCLUDED.description,
+                likes_count = EXCLUDED.likes_count,
+                is_album = EXCLUDED.is_album,
+                last_refreshed_at = now()",
+            &[
+                &self.playlist_id,
+                &self.sc_user_id,
+                &self.num_tracks,
+                &self.length_ms,
+                &self.created_at,
+                &self.title,
+                &self.permalink_url,
+                &self.description,
+                &self.likes_count,
+                &self.is_album
+            ],
+        )?;
+
+        client.execute("DELETE FROM playlist_tracks WHERE playlist_id = $1", &[&self.playlist_id])?;
+        client.execute(
+            "INSERT INTO playlist_tracks (playlist_id, track_id, position)
+            SELECT $1, t.track_id, (t.ord - 1)::INTEGER
+            FROM unnest($2::BIGINT[]) WITH ORDINALITY AS t(track_id, ord)",
+            &[&self.playlist_id, &self.track_ids]
+        )?;
+
         Ok(client.execute(
-            "CREATE TABLE IF NOT EXISTS playlists (
-                playlist_id     BIGINT PRIMARY KEY,
-                sc_user_id      BIGINT NOT NULL references soundcloudusers(sc_user_id),
-                track_ids       BIGINT[] NOT NULL,
-                num_tracks      BIGINT NOT NULL,
-                length_ms       BIGINT NOT NULL,
-                created_at      TEXT NOT NULL,
-                title           TEXT NOT NULL,
-                permalink_url   TEXT NOT NULL,
-                description     TEXT NOT NULL,
-                likes_count     BIGINT NOT NULL,
-                is_album        BOOLEAN NOT NULL
-            )",
-            &[]
+            "INSERT INTO playlist_stats_snapshots (playlist_id, likes_count, num_tracks)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING",
+            &[&self.playlist_id, &self.likes_count, &self.num_tracks]
         ).map(|_| ())?)
     }
 
-    /// Creates a new playlist in the database based on an instance of the struct
-    /// and a reference to the info about the playlist.
-    pub fn create_new(
+    /// Stores a playlist that was imported from an export bundle or dump,
+    /// whose metadata was last refreshed at the given time, if that's known.
+    ///
+    /// Unlike `create_new`, an already stored playlist (and its track list) is
+    /// only updated if the imported copy was refreshed more recently than the
+    /// stored one, no stats snapshot is added (the imported counts aren't
+    /// current), and the playlist's user isn't stored. Returns whether anything
+    /// was written.
+    pub fn import<C: GenericClient>(
         &self,
-        client: &mut Client,
-        playlist_info: &ScPlaylist
-    ) -> Result<(), Error> {
-        SoundCloudUser::from(playlist_info.user.as_ref().unwrap()).create_new(client)?;
-
-        for track in playlist_info.tracks.as_ref().unwrap() {
-            Track::from(track).create_new(client, &SoundCloudUser::from(track.user.as_ref().unwrap()))?;
-        }
-
-        Ok(client.execute(
-            "INSERT INTO playlists VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
-             ON CONFLICT DO NOTHING",
+        client: &mut C,
+        last_refreshed_at: Option<DateTime<Utc>>
+    ) -> Result<bool, Error> {
+        let written = client.execute(
+            "INSERT INTO playlists (
+                playlist_id, sc_user_id, num_tracks, length_ms, created_at,
+                title, permalink_url, description, likes_count, is_album,
+                last_refreshed_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, COALESCE($11, now()))
+            ON CONFLICT (playlist_id) DO UPDATE SET
+                sc_user_id = EXCLUDED.sc_user_id,
+                num_tracks = EXCLUDED.num_tracks,
+                length_ms = EXCLUDED.length_ms,
+                title = EXCLUDED.title,
+                permalink_url = EXCLUDED.permalink_url,
+                description = EXCLUDED.description,
+                likes_count = EXCLUDED.likes_count,
+                is_album = EXCLUDED.is_album,
+                last_refreshed_at = EXCLUDED.last_refreshed_at
+            WHERE playlists.last_refreshed_at < $11",
             &[
                 &self.playlist_id,
                 &self.sc_user_id,
-                &self.track_ids,
                 &self.num_tracks,
                 &self.length_ms,
                 &self.created_at,
@@ -584,9 +1111,22 @@ impl Playlist {
                 &self.permalink_url,
                 &self.description,
                 &self.likes_count,
-                &self.is_album
+                &self.is_album,
+                &last_refreshed_at
             ],
-        ).map(|_| ())?)
+        )? > 0;
+
+        if written {
+            client.execute("DELETE FROM playlist_tracks WHERE playlist_id = $1", &[&self.playlist_id])?;
+            client.execute(
+                "INSERT INTO playlist_tracks (playlist_id, track_id, position)
+                SELECT $1, t.track_id, (t.ord - 1)::INTEGER
+                FROM unnest($2::BIGINT[]) WITH ORDINALITY AS t(track_id, ord)",
+                &[&self.playlist_id, &self.track_ids]
+            )?;
+        }
+
+        Ok(written)
     }
 
     /// Loads the playlist specified by the given id from the database
@@ -595,7 +1135,11 @@ impl Playlist {
             SELECT
                 playlist_id,
                 sc_user_id,
-                track_ids,
+                ARRAY(
+                    SELECT track_id FROM playlist_tracks
+                    WHERE playlist_id = $1
+                    ORDER BY position
+                ),
                 num_tracks,
                 length_ms,
                 created_at,
@@ -624,3 +1168,176 @@ impl Playlist {
         })
     }
 }
+
+/// Representation of a scrape job in the database.
+///
+/// A row is recorded for every scrape that is requested so that its outcome
+/// can be looked up after the fact.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ScrapeJob {
+    /// A unique numeric id for the job
+    pub job_id: i32,
+    /// The id of the user whose data is being scraped
+    pub user_id: i32,
+    /// The state the job is currently in
+    pub status: ScrapeJobStatus,
+    /// How the job decides how much to scrape
+    pub mode: ScrapeMode,
+    /// How many recent likes to scrape, or `None` to scrape all of them
+    pub num_recent_likes: Option<i64>,
+    /// How many recent playlists to scrape, or `None` to scrape all of them
+    pub num_recent_playlists: Option<i64>,
+    /// When the job was requested
+    pub created_at: DateTime<Utc>,
+    /// When the job started running
+    pub started_at: Option<DateTime<Utc>>,
+    /// When the job stopped running, successfully or not
+    pub finished_at: Option<DateTime<Utc>>,
+    /// A description of the error that made the job fail
+    pub error: Option<String>
+}
+
+impl From<ScrapeJob> for ScrapeJobInfo {
+    fn from(j: ScrapeJob) -> Self {
+        Self {
+            job_id: j.job_id,
+            status: j.status,
+            mode: j.mode,
+            num_recent_likes: j.num_recent_likes,
+            num_recent_playlists: j.num_recent_playlists,
+            created_at: j.created_at,
+            started_at: j.started_at,
+            finished_at: j.finished_at,
+            error: j.error
+        }
+    }
+}
+
+impl ScrapeJob {
+    /// Builds a job from a row containing all of the table's columns, looked
+    /// up by name (see `User::from_row`).
+    fn from_row(row: &Row) -> Self {
+        let status: String = row.get("status");
+        let mode: String = row.get("mode");
+
+        Self {
+            job_id: row.get("job_id"),
+            user_id: row.get("user_id"),
+            status: status.parse().expect("unknown status in scrape_jobs table"),
+            mode: mode.parse().expect("unknown mode in scrape_jobs table"),
+            num_recent_likes: row.get("num_recent_likes"),
+            num_recent_playlists: row.get("num_recent_playlists"),
+            created_at: row.get("created_at"),
+            started_at: row.get("started_at"),
+            finished_at: row.get("finished_at"),
+            error: row.get("error")
+        }
+    }
+
+    /// Records a new, queued job for the given user and returns it.
+    ///
+    /// Errors with `Error::ScrapeAlreadyRunning` if the user already has a job
+    /// that hasn't finished.
+    pub fn create_new(
+        client: &mut Client,
+        user_id: i32,
+        mode: ScrapeMode,
+        num_recent_likes: Option<i64>,
+        num_recent_playlists: Option<i64>
+    ) -> Result<Self, Error> {
+        let result = client.query_one(
+            "INSERT INTO scrape_jobs (
+                user_id, status, mode, num_recent_likes, num_recent_playlists
+            ) VALUES ($1, $2, $3, $4, $5) RETURNING *",
+            &[
+                &user_id,
+                &ScrapeJobStatus::Queued.as_str(),
+                &mode.as_str(),
+                &num_recent_likes,
+                &num_recent_playlists
+            ]
+        );
+
+        match result {
+            Ok(row) => Ok(Self::from_row(&row)),
+            Err(e) if e.code() == Some(&SqlState::UNIQUE_VIOLATION) => Err(Error::ScrapeAlreadyRunning),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    /// Loads the job specified by the given id from the database
+    pub fn load_id(client: &mut Client, job_id: i32) -> Result<Self, Error> {
+        let row = client.query_one("SELECT * FROM scrape_jobs WHERE job_id = $1", &[&job_id])?;
+        Ok(Self::from_row(&row))
+    }
+
+    /// Loads the job specified by the given id, making sure that it belongs to
+    /// the given user.
+    pub fn load_id_for_user(client: &mut Client, job_id: i32, user_id: i32) -> Result<Self, Error> {
+        let maybe_row = client.query_opt(
+            "SELECT * FROM scrape_jobs WHERE job_id = $1 AND user_id = $2",
+            &[&job_id, &user_id]
+        )?;
+
+        maybe_row.map(|row| Self::from_row(&row)).ok_or(Error::ScrapeJobNotFound)
+    }
+
+    /// Loads all of the given user's jobs, most recent first.
+    pub fn load_all_for_user(client: &mut Client, user_id: i32) -> Result<Vec<Self>, Error> {
+        Ok(client.query(
+            "SELECT * FROM scrape_jobs WHERE user_id = $1 ORDER BY job_id DESC",
+            &[&user_id]
+        )?.iter().map(Self::from_row).collect())
+    }
+
+    /// Loads every job that is either queued or running.
+    pub fn load_unfinished(client: &mut Client) -> Result<Vec<Self>, Error> {
+        Ok(client.query(
+            "SELECT * FROM scrape_jobs WHERE status = $1 OR status = $2 ORDER BY job_id",
+            &[&ScrapeJobStatus::Queued.as_str(), &ScrapeJobStatus::Running.as_str()]
+        )?.iter().map(Self::from_row).collect())
+    }
+
+    /// Marks this job as having started running.
+    pub fn mark_running(&mut self, client: &mut Client) -> Result<(), Error> {
+        let row = client.query_one(
+            "UPDATE scrape_jobs SET status = $1, started_at = now() WHERE job_id = $2
+            RETURNING *",
+            &[&ScrapeJobStatus::Running.as_str(), &self.job_id]
+        )?;
+
+        *self = Self::from_row(&row);
+        Ok(())
+    }
+
+    /// Marks this job as finished, recording the error that stopped it if
+    /// there was one.
+    ///
+    /// `Error::ScrapeCancelled` marks the job as cancelled rather than failed.
+    /// Errors with `Error::ScrapeJobAlreadyFinished` if the job had already
+    /// finished by the time this got to it, in which case its outcome is kept.
+    pub fn mark_finished(&mut self, client: &mut Client, error: Option<&Error>) -> Result<(), Error> {
+        let (status, error) = match error {
+            Some(Error::ScrapeCancelled) => (ScrapeJobStatus::Cancelled, None),
+            Some(e) => (ScrapeJobStatus::Failed, Some(format!("{:?}", e))),
+            None => (ScrapeJobStatus::Succeeded, None)
+        };
+
+        let row = client.query_opt(
+            "UPDATE scrape_jobs SET status = $1, finished_at = now(), error = $2
+            WHERE job_id = $3 AND status IN ('queued', 'running') RETURNING *",
+            &[&status.as_str(), &error, &self.job_id]
+        )?.ok_or(Error::ScrapeJobAlreadyFinished)?;
+
+        *self = Self::from_row(&row);
+        Ok(())
+    }
+
+    /// Returns true if this job has stopped running, one way or another.
+    pub fn is_finished(&self) -> bool {
+        match self.status {
+            ScrapeJobStatus::Queued | ScrapeJobStatus::Running => false,
+            _ => true
+        }
+    }
+}
@@ -1,29 +1,56 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 #[macro_use] extern crate rocket;
 
+mod assets;
+mod audio;
+mod blob_store;
+mod bundle;
 mod database;
+mod migrations;
+mod range;
+mod s3_blob_store;
+mod scrape;
+mod schedule;
+mod soundcloud;
+mod verify;
 #[cfg(test)]
 mod test;
 
 use rocket_contrib::{json::Json, serve::StaticFiles};
-use rocket::{response::{status, NamedFile}, State, http::{Cookie, Status, Cookies}};
+use rocket::{response::{status, NamedFile}, State, http::{Cookie, ContentType, Status, Cookies}};
 use rocket::response::Responder;
+use rocket::Data;
 use json_structs::*;
 use dotenv::dotenv;
-use postgres::{Client, NoTls};
+use postgres::{NoTls, Row};
+use postgres::types::ToSql;
+use chrono::{DateTime, Utc};
 use postgres::fallible_iterator::FallibleIterator;
 use hyper_sse::Server;
 use lazy_static::lazy_static;
 use serde_derive::Serialize;
 
 
+use assets::*;
+use audio::*;
+use blob_store::*;
+use bundle::*;
 use database::*;
-
+use migrations::*;
+use range::*;
+use s3_blob_store::*;
+use scrape::*;
+use schedule::*;
+use soundcloud::*;
+use verify::*;
+
+use std::fs::File;
+use std::io::{self, Read};
 use std::path::PathBuf;
 use std::sync::{Mutex, Arc};
 use std::env;
 use std::thread;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 struct ArgonSecretKey(String);
 
@@ -56,12 +83,16 @@ pub enum Error {
     /// An error encountered while working with password hashes
     HashError(#[serde(skip_serializing)] argonautica::Error),
     PostgresErr(#[serde(skip_serializing)] postgres::error::Error),
+    /// No database connection could be checked out of the pool in time
+    PoolError(#[serde(skip_serializing)] r2d2::Error),
     JsonErr(#[serde(skip_serializing)] serde_json::Error),
     OrangeZestErr(orange_zest::Error),
     /// An attempt was made to create a user given details that already exist
     UserAlreadyExists,
     /// The backend did not have the auth tokens to do scraping for the requested user with
     ScAuthTokensNotPresent,
+    /// SoundCloud rejected the auth tokens stored for the user
+    ScAuthRejected,
     /// Could not log in with the given `LoginInfo`
     LoginFailed,
     /// Tried to access an API route that requires you to be authenticated
@@ -69,7 +100,70 @@ pub enum Error {
     /// Tried to make a request to a route that doesn't exist
     NonExistentApiRoute,
     /// The logged-in user has no liked tracks stored for their account
-    NoLikedTracksForUser
+    NoLikedTracksForUser,
+    /// The requested scrape job does not exist or belongs to another user
+    ScrapeJobNotFound,
+    /// The requested track is not in the logged-in user's archive
+    TrackNotFound,
+    /// The requested artist is not in the logged-in user's archive
+    ArtistNotFound,
+    /// The requested playlist is not stored
+    PlaylistNotFound,
+    /// The requested track's audio hasn't been archived
+    AudioNotArchived,
+    /// No image with the requested hash has been mirrored
+    AssetNotFound,
+    /// The logged-in user hasn't exported their archive yet
+    ExportNotFound,
+    /// The server was stopped while the scrape job was running
+    ScrapeInterrupted,
+    /// The scrape job was cancelled before it finished
+    ScrapeCancelled,
+    /// Tried to cancel a scrape job that had already finished
+    ScrapeJobAlreadyFinished,
+    /// Tried to start scraping for a user that already has a scrape job queued
+    /// or running
+    ScrapeAlreadyRunning,
+    /// The query parameter or request field with the given name had an
+    /// invalid value
+    InvalidParameter(String),
+    /// The uploaded import couldn't be read or doesn't make sense, for the
+    /// given reason
+    InvalidImport(String),
+    /// A request to SoundCloud could not be made
+    RequestError(String),
+    /// SoundCloud responded with an unexpected status code
+    HttpError(u16),
+    /// SoundCloud sent the given timestamp, which we couldn't make sense of
+    MalformedTimestamp(String),
+    /// SoundCloud sent something without the given field, which we can't do
+    /// without
+    MissingField(String),
+    /// The scrape job panicked before it could finish
+    ScrapePanicked,
+    /// The blob store is misconfigured or responded with something unexpected
+    BlobStoreError(String)
+}
+
+impl Error {
+    /// The status code that responses carrying this error are sent with
+    fn status(&self) -> Status {
+        match self {
+            Error::ScrapeJobNotFound => Status::NotFound,
+            Error::TrackNotFound => Status::NotFound,
+            Error::ArtistNotFound => Status::NotFound,
+            Error::PlaylistNotFound => Status::NotFound,
+            Error::AudioNotArchived => Status::NotFound,
+            Error::AssetNotFound => Status::NotFound,
+            Error::ExportNotFound => Status::NotFound,
+            Error::ScrapeJobAlreadyFinished => Status::Conflict,
+            Error::ScrapeAlreadyRunning => Status::Conflict,
+            Error::InvalidParameter(_) => Status::BadRequest,
+            Error::InvalidImport(_) => Status::BadRequest,
+            Error::PoolError(_) => Status::ServiceUnavailable,
+            _ => Status::InternalServerError
+        }
+    }
 }
 
 // TODO: figure out how to get the console to show outcome failed when responding
@@ -77,13 +171,20 @@ pub enum Error {
 impl<'r> Responder<'r> for Error {
     fn respond_to(self, req: &rocket::request::Request) -> rocket::response::Result<'r> {
         eprintln!("Responding with Err: {:?}", &self);
+        let status = self.status();
         Json(self).respond_to(req).map(|mut r| {
-            r.set_status(Status::InternalServerError);
+            r.set_status(status);
             r
         })
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::IoErr(err)
+    }
+}
+
 impl From<argonautica::Error> for Error {
     fn from(err: argonautica::Error) -> Self {
         Self::HashError(err)
@@ -96,6 +197,12 @@ impl From<postgres::error::Error> for Error {
     }
 }
 
+impl From<r2d2::Error> for Error {
+    fn from(err: r2d2::Error) -> Self {
+        Self::PoolError(err)
+    }
+}
+
 impl From<serde_json::Error> for Error {
     fn from(err: serde_json::Error) -> Self {
         Self::JsonErr(err)
@@ -113,10 +220,10 @@ impl From<orange_zest::Error> for Error {
 fn register(
     mut cookies: Cookies,
     reg_info: Json<RegisterInfo>,
-    db: State<DbClient>,
+    db: State<DbPool>,
     key: State<ArgonSecretKey>
 ) -> Result<Json<UserInfo>, Error> {
-    let mut client = db.lock().unwrap();
+    let mut client = db.get()?;
     let user_id = User::create_new(&mut client, &reg_info, &key.0)?;
 
     let user_info = UserInfo {
@@ -132,10 +239,10 @@ fn register(
 fn login(
     mut cookies: Cookies,
     login_info: Json<LoginInfo>,
-    db: State<DbClient>,
+    db: State<DbPool>,
     key: State<ArgonSecretKey>
 ) -> Result<Json<UserInfo>, Error> {
-    let mut client = db.lock().unwrap();
+    let mut client = db.get()?;
     let user = User::load_username(&mut client, &login_info.username)?;
 
     if user.auth(&login_info, &key.0)? {
@@ -196,8 +303,8 @@ fn not_found() -> NamedFile {
 /// any route with a `User` parameter).
 // TODO: validate that tokens work before storing
 #[post("/set-auth-creds", format = "json", data = "<auth_creds>")]
-fn set_auth_creds(user: User, db: State<DbClient>, auth_creds: Json<AuthCredentials>) -> Result<(), Error> {
-    let mut client = db.lock().unwrap();
+fn set_auth_creds(user: User, db: State<DbPool>, auth_creds: Json<AuthCredentials>) -> Result<(), Error> {
+    let mut client = db.get()?;
     user.store_sc_credentials(&mut client, &auth_creds)
 }
 
@@ -227,184 +334,701 @@ fn sse_auth_token(user: User) -> Result<String, Error> {
 ///
 /// If the query parameters are not specified, this route scrapes all available
 /// info.
+///
+/// The `mode` query parameter can be set to `incremental` to only scrape what's
+/// new since the last scrape: likes and playlists are fetched newest-first
+/// until one that is already archived shows up. This makes routine refreshes
+/// cheap. In this mode the `num_recent_*` parameters are upper bounds on how
+/// far back to look. `mode` defaults to `full`; verification and audio
+/// archiving jobs are started with `/verify-tracks` and `/archive-audio`
+/// instead.
+///
+/// The scraping itself happens in the background as a scrape job. This route
+/// responds immediately with the newly-created job; its progress and outcome
+/// can be looked up later via `/scrape-jobs/<id>`. The stored credentials are
+/// checked with SoundCloud first, though, and if it rejects them this route
+/// responds with `HttpError` and the status SoundCloud responded with.
+///
+/// Only one scrape job can be queued or running for a user at a time. While
+/// one is, this route responds with a 409 Conflict and `ScrapeAlreadyRunning`.
 /// 
 /// SSE events are sent to the client if you have registered to receive them. All
 /// events are sent with an event name of "update".
-#[get("/do-scraping?<num_recent_likes>&<num_recent_playlists>")]
+#[get("/do-scraping?<num_recent_likes>&<num_recent_playlists>&<mode>")]
 fn do_scraping(
     user: User,
-    db: State<DbClient>,
+    worker: State<ScrapeWorker>,
     num_recent_likes: Option<u64>,
-    num_recent_playlists: Option<u64>
-) -> Result<(), Error> {
-    let db = db.clone();
-    let num_recent_likes = num_recent_likes.unwrap_or(std::u64::MAX);
-    let num_recent_playlists = num_recent_playlists.unwrap_or(std::u64::MAX);
-
-    let mut liked_track_ids = HashSet::new();
-    for id in &user.liked_track_ids {
-        liked_track_ids.insert(*id);
-    }
-
-    let mut playlist_ids = HashSet::new();
-    for id in &user.playlist_ids {
-        playlist_ids.insert(*id);
-    }
-
-    if let (Some(oauth_token), Some(client_id)) = (user.sc_oauth_token.clone(), user.sc_client_id.clone()) {
-        let zester = orange_zest::Zester::new(oauth_token, client_id)?;
-
-        thread::spawn(move || -> Result<(), Error> {
-            let likes = zester.likes(num_recent_likes, |e| {
-                // We don't really care about errors here
-                let _ = SSE.push(
-                    user.user_id,
-                    "update",
-                    &SseEvent::LikesScrapingEvent(e)
-                );
-            })?;
-            let playlists = zester.playlists(num_recent_playlists, |e| {
-                // We don't really care about errors here
-                let _ = SSE.push(
-                    user.user_id,
-                    "update",
-                    &SseEvent::PlaylistsScrapingEvent(e)
-                );
-            })?;
-
-            {
-                let mut conn = db.lock().unwrap();
-
-                for track in likes.collections.iter().map(|c| &c.track) {
-                    Track::from(track).create_new(
-                        &mut conn,
-                        &SoundCloudUser::from(track.user.as_ref().unwrap())
-                    ).unwrap();
-
-                    liked_track_ids.insert(track.id.unwrap());
-                }
-
-                user.update_liked_track_ids(&mut conn, liked_track_ids)?;
-
-                for playlist in playlists.playlists.iter() {
-                    Playlist::from(playlist).create_new(
-                        &mut conn,
-                        &playlist
-                    ).unwrap();
-
-                    playlist_ids.insert(playlist.id.unwrap());
-                }
-
-                user.update_playlist_ids(&mut conn, playlist_ids)?;
-            }
-
-            let _ = SSE.push(
-                user.user_id,
-                "update",
-                &SseEvent::Complete
-            );
-
-            Ok(())
-        });
-
-        Ok(())
-    } else {
-        Err(Error::ScAuthTokensNotPresent)
+    num_recent_playlists: Option<u64>,
+    mode: Option<String>
+) -> Result<Json<ScrapeJobInfo>, Error> {
+    let mode = match mode {
+        Some(m) => m.parse().map_err(|_| Error::InvalidParameter("mode".into()))?,
+        None => ScrapeMode::Full
+    };
+    // Anything that doesn't fit in postgres' BIGINT is as good as "everything"
+    let to_limit = |n: u64| if n > std::i64::MAX as u64 { None } else { Some(n as i64) };
+
+    if mode != ScrapeMode::Full && mode != ScrapeMode::Incremental {
+        return Err(Error::InvalidParameter("mode".into()));
     }
+
+    // Make sure SoundCloud accepts the stored credentials before queueing
+    // anything, so that bad ones are reported right away rather than by a
+    // failed job
+    ScApi::for_user(&user)?.own_user_id()?;
+    let job = worker.enqueue(
+        &user,
+        mode,
+        num_recent_likes.and_then(to_limit),
+        num_recent_playlists.and_then(to_limit)
+    )?;
+
+    Ok(Json(job.into()))
 }
 
-/// Get a list of all the logged-in user's liked tracks
-#[get("/liked-tracks")]
-fn liked_tracks(user: User, db: State<DbClient>) -> Result<Json<Vec<TrackInfoBrief>>, Error> {
-    let mut conn = db.lock().unwrap();
-    let result = conn.query("
-        SELECT tracks.track_id, tracks.length_ms, tracks.created_at, tracks.title,
-            tracks.playback_count, soundcloudusers.sc_user_id, soundcloudusers.username
-        FROM tracks, soundcloudusers
-        WHERE track_id = ANY($1) AND tracks.sc_user_id = soundcloudusers.sc_user_id
-    ", &[&user.liked_track_ids])?;
+/// Get a list of the logged-in user's scrape jobs, most recent first
+#[get("/scrape-jobs")]
+fn scrape_jobs(user: User, db: State<DbPool>) -> Result<Json<Vec<ScrapeJobInfo>>, Error> {
+    let mut conn = db.get()?;
+    let jobs = ScrapeJob::load_all_for_user(&mut conn, user.user_id)?;
+
+    Ok(Json(jobs.into_iter().map(ScrapeJobInfo::from).collect()))
+}
+
+/// Get information about a specific scrape job of the logged-in user
+#[get("/scrape-jobs/<id>")]
+fn scrape_job(user: User, db: State<DbPool>, id: i32) -> Result<Json<ScrapeJobInfo>, Error> {
+    let mut conn = db.get()?;
+    Ok(Json(ScrapeJob::load_id_for_user(&mut conn, id, user.user_id)?.into()))
+}
+
+/// Cancel a queued or running scrape job of the logged-in user.
+///
+/// Cancellation is cooperative: a running job stops at the next point where it
+/// checks for cancellation, so the job returned by this route may still be
+/// running. Nothing a cancelled job fetched is stored, and a "Cancelled" SSE
+/// event is sent once it has stopped.
+#[post("/scrape-jobs/<id>/cancel")]
+fn cancel_scrape_job(
+    user: User,
+    worker: State<ScrapeWorker>,
+    id: i32
+) -> Result<Json<ScrapeJobInfo>, Error> {
+    Ok(Json(worker.cancel(&user, id)?.into()))
+}
+
+/// Check which of the logged-in user's archived tracks are still available on
+/// SoundCloud.
+///
+/// Every track in the user's liked tracks, like history and playlists is
+/// looked up on SoundCloud and marked as available, deleted or private. Like
+/// scraping, this runs in the background as a scrape job (with a mode of
+/// `verify`) and counts towards the one-job-per-user limit.
+/// "VerificationProgress" SSE events are sent as tracks are checked.
+///
+/// The results can be seen in `/lost-tracks`.
+#[post("/verify-tracks")]
+fn verify_tracks(user: User, worker: State<ScrapeWorker>) -> Result<Json<ScrapeJobInfo>, Error> {
+    // Make sure the stored credentials are there before queueing anything
+    ScApi::for_user(&user)?;
+    let job = worker.enqueue(&user, ScrapeMode::Verify, None, None)?;
+
+    Ok(Json(job.into()))
+}
+
+/// Download the audio of the logged-in user's liked tracks.
+///
+/// The audio of every currently liked track that doesn't have its audio
+/// archived yet is downloaded from SoundCloud into the blob store. Tracks that
+/// SoundCloud only streams in segments are skipped. Like scraping, this runs
+/// in the background as a scrape job (with a mode of `archive_audio`) and
+/// counts towards the one-job-per-user limit. "AudioArchivingProgress" SSE
+/// events are sent as tracks are downloaded.
+///
+/// The audio can be listened to with `/tracks/<id>/audio`.
+#[post("/archive-audio")]
+fn archive_audio(user: User, worker: State<ScrapeWorker>) -> Result<Json<ScrapeJobInfo>, Error> {
+    // Make sure the stored credentials are there before queueing anything
+    ScApi::for_user(&user)?;
+    let job = worker.enqueue(&user, ScrapeMode::ArchiveAudio, None, None)?;
+
+    Ok(Json(job.into()))
+}
+
+/// Get the archived audio of a specific track
+///
+/// Supports `Range` requests for a single range of bytes, so the audio can be
+/// seeked through in a browser's audio player.
+///
+/// Responds with a 404 Not Found and `TrackNotFound` if the track isn't in the
+/// user's archive (it isn't liked, wasn't liked before and isn't in any of the
+/// user's playlists), and with `AudioNotArchived` if the track's audio hasn't
+/// been archived.
+#[get("/tracks/<id>/audio")]
+fn track_audio(
+    user: User,
+    db: State<DbPool>,
+    store: State<SharedBlobStore>,
+    range: RangeHeader,
+    id: i64
+) -> Result<RangedBlob, Error> {
+    let audio = {
+        let mut conn = db.get()?;
+        let archived: bool = conn.query_one("
+            SELECT EXISTS (SELECT 1 FROM user_likes WHERE user_id = $1 AND track_id = $2)
+                OR EXISTS (
+                    SELECT 1
+                    FROM playlist_tracks pt, user_playlists up
+                    WHERE up.user_id = $1 AND pt.playlist_id = up.playlist_id AND pt.track_id = $2
+                )
+        ", &[&user.user_id, &id])?.get(0);
+        if !archived {
+            return Err(Error::TrackNotFound);
+        }
+
+        TrackAudio::load_for_track(&mut conn, id)?.ok_or(Error::AudioNotArchived)?
+    };
+
+    Ok(RangedBlob {
+        blob: store.open(&audio.hash)?,
+        content_type: audio.content_type.parse().unwrap_or(ContentType::Binary),
+        range: range.0,
+        download_name: None
+    })
+}
+
+/// Bundle up the logged-in user's archive for downloading.
+///
+/// The user's likes, tracks, playlists and artists are bundled into a gzipped
+/// tarball as JSON, together with the archived audio and mirrored images that
+/// belong to them (see `ExportManifest` for the layout). Like scraping, this
+/// runs in the background as a scrape job (with a mode of `export`) and counts
+/// towards the one-job-per-user limit. "ExportProgress" SSE events are sent as
+/// media files are added.
+///
+/// Once the job is done, the bundle can be downloaded from `/export`. Only the
+/// latest bundle is kept.
+#[post("/export")]
+fn start_export(user: User, worker: State<ScrapeWorker>) -> Result<Json<ScrapeJobInfo>, Error> {
+    let job = worker.enqueue(&user, ScrapeMode::Export, None, None)?;
+
+    Ok(Json(job.into()))
+}
+
+/// Download the logged-in user's latest export bundle
+///
+/// Supports `Range` requests for a single range of bytes, so an interrupted
+/// download of a big bundle can be resumed.
+///
+/// Responds with a 404 Not Found and `ExportNotFound` if the user hasn't
+/// exported their archive yet.
+#[get("/export")]
+fn export_bundle(
+    user: User,
+    db: State<DbPool>,
+    store: State<SharedBlobStore>,
+    range: RangeHeader
+) -> Result<RangedBlob, Error> {
+    let export = {
+        let mut conn = db.get()?;
+        Export::load_for_user(&mut conn, user.user_id)?.ok_or(Error::ExportNotFound)?
+    };
+
+    Ok(RangedBlob {
+        blob: store.open(&export.hash)?,
+        content_type: ContentType::new("application", "gzip"),
+        range: range.0,
+        download_name: Some(format!("crate-export-{}.tar.gz", export.created_at.format("%Y-%m-%d")))
+    })
+}
+
+/// The largest upload `/import` accepts, in bytes
+const MAX_IMPORT_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Import an export bundle, or a JSON dump of likes or playlists made by
+/// orange-zest, into the logged-in user's archive
+///
+/// The body is either a bundle as downloaded from `/export` (possibly from
+/// another deployment), or a `Likes` or `Playlists` dump. What's in it is
+/// merged into the archive without overwriting anything newer; see
+/// `ImportReport` for what is kept and what is updated. Everything is checked
+/// before anything is stored, and a bad upload, or one larger than
+/// `MAX_IMPORT_BYTES` or with JSON larger than `MAX_IMPORT_JSON_BYTES`, is
+/// rejected with a 400 Bad Request and `InvalidImport`.
+///
+/// The import is recorded as a scrape job in "import" mode, so it can be
+/// cancelled like one. Only one job can be active per user, so if one already
+/// is, this route responds with a 409 Conflict and `ScrapeAlreadyRunning`.
+///
+/// With `dry_run=true` nothing is changed, and the response says what would
+/// have changed. "ImportProgress" SSE events are sent as things are imported.
+#[post("/import?<dry_run>", data = "<data>")]
+fn import(
+    user: User,
+    db: State<DbPool>,
+    store: State<SharedBlobStore>,
+    worker: State<ScrapeWorker>,
+    dry_run: Option<bool>,
+    data: Data
+) -> Result<Json<ImportReport>, Error> {
+    let spool_path = env::temp_dir().join(format!("crate-import-{}", temp_file_name()));
+    let imported = worker.run_now(&user, ScrapeMode::Import, |_, cancelled| {
+        // One byte more than the limit is read, to tell whether there's more
+        let len = File::create(&spool_path)
+            .and_then(|mut file| io::copy(&mut data.open().take(MAX_IMPORT_BYTES + 1), &mut file))?;
+        if len > MAX_IMPORT_BYTES {
+            return Err(Error::InvalidImport(format!("the upload is larger than {} bytes", MAX_IMPORT_BYTES)));
+        }
+
+        import_archive(&db, &**store, &user, &spool_path, dry_run.unwrap_or(false), cancelled)
+    });
+    let _ = std::fs::remove_file(&spool_path);
+
+    Ok(Json(imported?))
+}
+
+/// Get how often the logged-in user's data is scraped automatically
+#[get("/scrape-schedule")]
+fn scrape_schedule(user: User) -> Json<ScrapeScheduleInfo> {
+    Json(ScrapeScheduleInfo {
+        schedule: user.scrape_schedule,
+        next_run_at: user.next_scheduled_scrape_at
+    })
+}
+
+/// Change how often the logged-in user's data is scraped automatically.
+///
+/// Scheduled scrapes are incremental (see `/do-scraping`) and use the
+/// credentials posted to `/set-auth-creds`. Turning the schedule on makes the
+/// first scheduled scrape happen within a few minutes.
+#[post("/scrape-schedule", format = "json", data = "<settings>")]
+fn set_scrape_schedule(
+    mut user: User,
+    db: State<DbPool>,
+    settings: Json<ScrapeScheduleSettings>
+) -> Result<Json<ScrapeScheduleInfo>, Error> {
+    let mut conn = db.get()?;
+    user.set_scrape_schedule(&mut conn, settings.schedule)?;
+
+    Ok(Json(ScrapeScheduleInfo {
+        schedule: user.scrape_schedule,
+        next_run_at: user.next_scheduled_scrape_at
+    }))
+}
 
-    Ok(Json(result.into_iter().map(|r| TrackInfoBrief {
+/// The columns that `track_info_brief` reads, in order
+///
+/// Queries selecting these need to have the `tracks` and `soundcloudusers`
+/// tables in scope under those names.
+const TRACK_INFO_BRIEF_COLUMNS: &str = "
+    tracks.track_id, tracks.length_ms, tracks.created_at, tracks.title,
+    tracks.playback_count, soundcloudusers.sc_user_id, soundcloudusers.username,
+    tracks.availability, tracks.last_verified_at";
+
+/// The number of columns in `TRACK_INFO_BRIEF_COLUMNS`
+const TRACK_INFO_BRIEF_LEN: usize = 9;
+
+/// Builds a `TrackInfoBrief` from a row that starts with the columns in
+/// `TRACK_INFO_BRIEF_COLUMNS`
+fn track_info_brief(r: &Row) -> TrackInfoBrief {
+    let availability: String = r.get(7);
+
+    TrackInfoBrief {
         track_id: r.get(0),
         length_ms: r.get(1),
         created_at: r.get(2),
         title: r.get(3),
         playback_count: r.get(4),
         sc_user_id: r.get(5),
-        username: r.get(6)
+        username: r.get(6),
+        availability: availability.parse().expect("invalid track availability in database"),
+        last_verified_at: r.get(8)
+    }
+}
+
+/// The columns that `playlist_info_brief` reads, in order
+///
+/// Queries selecting these need to have the `playlists` and `soundcloudusers`
+/// tables in scope under those names.
+const PLAYLIST_INFO_BRIEF_COLUMNS: &str = "
+    playlists.playlist_id, playlists.length_ms, playlists.created_at,
+    playlists.title, playlists.is_album, playlists.num_tracks,
+    soundcloudusers.sc_user_id, soundcloudusers.username";
+
+/// Builds a `PlaylistInfoBrief` from a row that starts with the columns in
+/// `PLAYLIST_INFO_BRIEF_COLUMNS`
+fn playlist_info_brief(r: &Row) -> PlaylistInfoBrief {
+    PlaylistInfoBrief {
+        playlist_id: r.get(0),
+        length_ms: r.get(1),
+        created_at: r.get(2),
+        title: r.get(3),
+        is_album: r.get(4),
+        num_tracks: r.get(5),
+        sc_user_id: r.get(6),
+        username: r.get(7)
+    }
+}
+
+/// How many liked tracks `/liked-tracks` lists at once unless asked otherwise
+const DEFAULT_LIKED_TRACKS_LIMIT: i64 = 100;
+/// The most liked tracks `/liked-tracks` will list at once
+const MAX_LIKED_TRACKS_LIMIT: i64 = 1000;
+
+/// Get a page of the logged-in user's liked tracks
+///
+/// The `filter` query parameter chooses which likes to list: `current` (the
+/// default) lists tracks that are liked right now, `removed` lists tracks that
+/// were liked once but have been unliked on SoundCloud since, and `all` lists
+/// both.
+///
+/// `sort` can be `liked_at` (the default), `created_at`, `playback_count`,
+/// `length_ms` or `title`, and `order` can be `desc` (the default) or `asc`.
+/// Likes whose time we never found out about come after all the others when
+/// sorting by `liked_at`.
+///
+/// The list can be narrowed down to the tracks of one artist with `artist`, to
+/// tracks of a certain length with `min_length_ms` and `max_length_ms`, and to
+/// tracks uploaded in a certain time span with `created_after` and
+/// `created_before` (as RFC 3339 dates).
+///
+/// At most `limit` tracks are listed at once (100 by default, 1000 at most).
+/// The `next_cursor` of the response can be passed as `cursor` to get the next
+/// page.
+#[get("/liked-tracks?<filter>&<limit>&<cursor>&<sort>&<order>&<artist>&<min_length_ms>&<max_length_ms>&<created_after>&<created_before>")]
+fn liked_tracks(
+    user: User,
+    db: State<DbPool>,
+    filter: Option<String>,
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<String>,
+    order: Option<String>,
+    artist: Option<i64>,
+    min_length_ms: Option<i64>,
+    max_length_ms: Option<i64>,
+    created_after: Option<String>,
+    created_before: Option<String>
+) -> Result<Json<Page<TrackInfoBrief>>, Error> {
+    let filter = match filter {
+        Some(f) => f.parse().map_err(|_| Error::InvalidParameter("filter".into()))?,
+        None => LikeFilter::Current
+    };
+    let sort = match sort {
+        Some(s) => s.parse().map_err(|_| Error::InvalidParameter("sort".into()))?,
+        None => LikeSort::LikedAt
+    };
+    let order: SortOrder = match order {
+        Some(o) => o.parse().map_err(|_| Error::InvalidParameter("order".into()))?,
+        None => SortOrder::Descending
+    };
+
+    let limit = limit.unwrap_or(DEFAULT_LIKED_TRACKS_LIMIT);
+    if limit < 1 || limit > MAX_LIKED_TRACKS_LIMIT {
+        return Err(Error::InvalidParameter("limit".into()));
+    }
+    // The cursor is just the offset of the next page, but clients shouldn't
+    // rely on that
+    let offset: i64 = match cursor {
+        Some(c) => c.parse().ok().filter(|o| *o >= 0).ok_or_else(|| Error::InvalidParameter("cursor".into()))?,
+        None => 0
+    };
+
+    let parse_date = |name: &str, date: Option<String>| -> Result<Option<DateTime<Utc>>, Error> {
+        date.map(|d| d.parse().map_err(|_| Error::InvalidParameter(name.into()))).transpose()
+    };
+    let created_after = parse_date("created_after", created_after)?;
+    let created_before = parse_date("created_before", created_before)?;
+
+    let mut conditions = vec![
+        "l.user_id = $1".to_string(),
+        "l.track_id = tracks.track_id".to_string(),
+        "tracks.sc_user_id = soundcloudusers.sc_user_id".to_string()
+    ];
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![&user.user_id];
+
+    match filter {
+        LikeFilter::Current => conditions.push("l.removed_at IS NULL".into()),
+        LikeFilter::Removed => conditions.push("l.removed_at IS NOT NULL".into()),
+        LikeFilter::All => {}
+    }
+    if let Some(artist) = &artist {
+        params.push(artist);
+        conditions.push(format!("tracks.sc_user_id = ${}", params.len()));
+    }
+    if let Some(min_length_ms) = &min_length_ms {
+        params.push(min_length_ms);
+        conditions.push(format!("tracks.length_ms >= ${}", params.len()));
+    }
+    if let Some(max_length_ms) = &max_length_ms {
+        params.push(max_length_ms);
+        conditions.push(format!("tracks.length_ms <= ${}", params.len()));
+    }
+    if let Some(created_after) = &created_after {
+        params.push(created_after);
+        conditions.push(format!("tracks.created_at >= ${}", params.len()));
+    }
+    if let Some(created_before) = &created_before {
+        params.push(created_before);
+        conditions.push(format!("tracks.created_at < ${}", params.len()));
+    }
+    let conditions = conditions.join(" AND ");
+
+    let sort_column = match sort {
+        LikeSort::LikedAt => "l.liked_at",
+        LikeSort::CreatedAt => "tracks.created_at",
+        LikeSort::PlaybackCount => "tracks.playback_count",
+        LikeSort::LengthMs => "tracks.length_ms",
+        LikeSort::Title => "tracks.title"
+    };
+
+    let mut conn = db.get()?;
+    let total: i64 = conn.query_one(format!("
+        SELECT COUNT(*)
+        FROM user_likes l, tracks, soundcloudusers
+        WHERE {}
+    ", conditions).as_str(), &params)?.get(0);
+
+    // Ties are broken by something unique so that pages don't overlap
+    let result = conn.query(format!("
+        SELECT {}
+        FROM user_likes l, tracks, soundcloudusers
+        WHERE {}
+        ORDER BY {} {} NULLS LAST, l.position, l.removed_at DESC, tracks.track_id
+        LIMIT {} OFFSET {}
+    ", TRACK_INFO_BRIEF_COLUMNS, conditions, sort_column, order.as_sql(), limit, offset).as_str(), &params)?;
+
+    let items: Vec<TrackInfoBrief> = result.iter().map(track_info_brief).collect();
+    let next_offset = offset + items.len() as i64;
+
+    Ok(Json(Page {
+        next_cursor: if next_offset < total { Some(next_offset.to_string()) } else { None },
+        items,
+        total
+    }))
+}
+
+/// Get the history of the logged-in user's likes
+///
+/// Every track that a scrape has seen among the user's likes is listed along
+/// with when it was first seen and, if it has been unliked since, when that
+/// was noticed. The most recent changes come first.
+///
+/// Unlikes are only noticed by full scrapes of all of a user's likes.
+#[get("/like-history")]
+fn like_history(user: User, db: State<DbPool>) -> Result<Json<Vec<LikeHistoryEntry>>, Error> {
+    let mut conn = db.get()?;
+    let result = conn.query(format!("
+        SELECT {}, l.first_seen, l.removed_at
+        FROM user_likes l, tracks, soundcloudusers
+        WHERE l.user_id = $1 AND l.track_id = tracks.track_id
+            AND tracks.sc_user_id = soundcloudusers.sc_user_id
+        ORDER BY GREATEST(l.first_seen, l.removed_at) DESC
+    ", TRACK_INFO_BRIEF_COLUMNS).as_str(), &[&user.user_id])?;
+
+    Ok(Json(result.iter().map(|r| LikeHistoryEntry {
+        track: track_info_brief(r),
+        first_seen: r.get(TRACK_INFO_BRIEF_LEN),
+        removed_at: r.get(TRACK_INFO_BRIEF_LEN + 1)
     }).collect()))
 }
 
+/// Get the logged-in user's archived tracks that are no longer available on
+/// SoundCloud
+///
+/// Covers every track in the user's liked tracks, like history and playlists
+/// that the last verification found to be deleted or private. Tracks that have
+/// never been verified are assumed to still be available.
+#[get("/lost-tracks")]
+fn lost_tracks(user: User, db: State<DbPool>) -> Result<Json<Vec<TrackInfoBrief>>, Error> {
+    let mut conn = db.get()?;
+    let track_ids = Track::archived_ids_for_user(&mut conn, &user)?;
+
+    let result = conn.query(format!("
+        SELECT {}
+        FROM tracks, soundcloudusers
+        WHERE track_id = ANY($1) AND tracks.availability <> 'available'
+            AND tracks.sc_user_id = soundcloudusers.sc_user_id
+        ORDER BY tracks.last_verified_at DESC
+    ", TRACK_INFO_BRIEF_COLUMNS).as_str(), &[&track_ids])?;
+
+    Ok(Json(result.iter().map(track_info_brief).collect()))
+}
+
+/// The columns that `track_info_long` reads after `TRACK_INFO_BRIEF_COLUMNS`,
+/// in order
+const TRACK_INFO_LONG_COLUMNS: &str = "
+    tracks.description, tracks.likes_count, tracks.artwork_url, tracks.permalink_url,
+    soundcloudusers.avatar_url, soundcloudusers.full_name, soundcloudusers.permalink_url,
+    tracks.last_refreshed_at,
+    (SELECT hash FROM assets WHERE url = tracks.artwork_url),
+    (SELECT hash FROM assets WHERE url = soundcloudusers.avatar_url)";
+
+/// Builds a `TrackInfoLong` from a row that starts with the columns in
+/// `TRACK_INFO_BRIEF_COLUMNS` followed by the ones in `TRACK_INFO_LONG_COLUMNS`
+fn track_info_long(r: &Row) -> TrackInfoLong {
+    let n = TRACK_INFO_BRIEF_LEN;
+
+    TrackInfoLong {
+        brief_info: track_info_brief(r),
+        description: r.get(n),
+        likes_count: r.get(n + 1),
+        artwork_url: r.get(n + 2),
+        track_permalink_url: r.get(n + 3),
+        avatar_url: r.get(n + 4),
+        full_name: r.get(n + 5),
+        user_permalink_url: r.get(n + 6),
+        last_refreshed_at: r.get(n + 7),
+        local_artwork_url: r.get::<_, Option<String>>(n + 8).map(|h| local_asset_url(&h)),
+        local_avatar_url: r.get::<_, Option<String>>(n + 9).map(|h| local_asset_url(&h))
+    }
+}
+
+/// The columns that `sc_user_info` reads, in order
+///
+/// Queries selecting these need to have the `soundcloudusers` table in scope
+/// under that name.
+const SC_USER_INFO_COLUMNS: &str = "
+    soundcloudusers.sc_user_id, soundcloudusers.avatar_url, soundcloudusers.full_name,
+    soundcloudusers.username, soundcloudusers.permalink_url,
+    (SELECT hash FROM assets WHERE url = soundcloudusers.avatar_url)";
+
+/// The number of columns in `SC_USER_INFO_COLUMNS`
+const SC_USER_INFO_LEN: usize = 6;
+
+/// Builds a `ScUserInfo` from a row that starts with the columns in
+/// `SC_USER_INFO_COLUMNS`
+fn sc_user_info(r: &Row) -> ScUserInfo {
+    ScUserInfo {
+        sc_user_id: r.get(0),
+        avatar_url: r.get(1),
+        full_name: r.get(2),
+        username: r.get(3),
+        permalink_url: r.get(4),
+        local_avatar_url: r.get::<_, Option<String>>(5).map(|h| local_asset_url(&h))
+    }
+}
+
+/// Loads the `ScUserInfo` of the SoundCloud user with the given id
+fn load_sc_user_info(client: &mut postgres::Client, sc_user_id: i64) -> Result<ScUserInfo, Error> {
+    let r = client.query_one(format!("
+        SELECT {}
+        FROM soundcloudusers
+        WHERE sc_user_id = $1
+    ", SC_USER_INFO_COLUMNS).as_str(), &[&sc_user_id])?;
+
+    Ok(sc_user_info(&r))
+}
+
+/// The most tracks `/track-info/batch` will look up at once
+const MAX_TRACK_INFO_BATCH: usize = 1000;
+
 /// Get detailed information for a specific track
 #[get("/track-info/<id>")]
-fn track_info(_user: User, db: State<DbClient>, id: i64) -> Result<Json<TrackInfoLong>, Error> {
-    let mut conn = db.lock().unwrap();
-    let r = conn.query_one("
-        SELECT tracks.track_id, tracks.length_ms, tracks.created_at, tracks.title,
-            tracks.playback_count, soundcloudusers.sc_user_id, soundcloudusers.username,
-            tracks.description, tracks.likes_count, tracks.artwork_url, tracks.permalink_url,
-            soundcloudusers.avatar_url, soundcloudusers.full_name, soundcloudusers.permalink_url
+fn track_info(_user: User, db: State<DbPool>, id: i64) -> Result<Json<TrackInfoLong>, Error> {
+    let mut conn = db.get()?;
+    let r = conn.query_one(format!("
+        SELECT {}, {}
         FROM tracks, soundcloudusers
         WHERE track_id = $1 AND tracks.sc_user_id = soundcloudusers.sc_user_id
-    ", &[&id])?;
+    ", TRACK_INFO_BRIEF_COLUMNS, TRACK_INFO_LONG_COLUMNS).as_str(), &[&id])?;
 
-    Ok(Json(TrackInfoLong {
-        brief_info: TrackInfoBrief {
-            track_id: r.get(0),
-            length_ms: r.get(1),
-            created_at: r.get(2),
-            title: r.get(3),
-            playback_count: r.get(4),
-            sc_user_id: r.get(5),
-            username: r.get(6)
-        },
-        description: r.get(7),
-        likes_count: r.get(8),
-        artwork_url: r.get(9),
-        track_permalink_url: r.get(10),
-        avatar_url: r.get(11),
-        full_name: r.get(12),
-        user_permalink_url: r.get(13)
-    }))
+    Ok(Json(track_info_long(&r)))
 }
 
-/// Get a list of all the logged-in user's liked and owned playlists
-#[get("/liked-and-owned-playlists")]
-fn liked_and_owned_playlists(user: User, db: State<DbClient>) -> Result<Json<Vec<PlaylistInfoBrief>>, Error> {
-    let mut conn = db.lock().unwrap();
+/// Get detailed information for many tracks at once
+///
+/// Takes a JSON list of track ids (at most 1000) and responds with an object
+/// that maps each of them to either `{"Found": <track info>}` or `"NotFound"`.
+#[post("/track-info/batch", format = "json", data = "<track_ids>")]
+fn track_info_batch(
+    _user: User,
+    db: State<DbPool>,
+    track_ids: Json<Vec<i64>>
+) -> Result<Json<HashMap<i64, TrackInfoLookup>>, Error> {
+    if track_ids.len() > MAX_TRACK_INFO_BATCH {
+        return Err(Error::InvalidParameter("track_ids".into()));
+    }
+
+    let mut conn = db.get()?;
+    let result = conn.query(format!("
+        SELECT {}, {}
+        FROM tracks, soundcloudusers
+        WHERE track_id = ANY($1) AND tracks.sc_user_id = soundcloudusers.sc_user_id
+    ", TRACK_INFO_BRIEF_COLUMNS, TRACK_INFO_LONG_COLUMNS).as_str(), &[&*track_ids])?;
+
+    let mut lookups: HashMap<i64, TrackInfoLookup> = track_ids
+        .iter()
+        .map(|id| (*id, TrackInfoLookup::NotFound))
+        .collect();
+
+    for r in result.iter() {
+        let info = track_info_long(r);
+        lookups.insert(info.brief_info.track_id, TrackInfoLookup::Found(info));
+    }
+
+    Ok(Json(lookups))
+}
+
+/// Get the play and like counts of a specific track over time
+///
+/// A snapshot is taken every time the track is scraped. Snapshots are listed
+/// oldest first.
+#[get("/track-info/<id>/history")]
+fn track_history(_user: User, db: State<DbPool>, id: i64) -> Result<Json<Vec<TrackStatsSnapshot>>, Error> {
+    let mut conn = db.get()?;
     let result = conn.query("
-        SELECT p.playlist_id, p.length_ms, p.created_at, p.title, p.is_album,
-            p.num_tracks, u.sc_user_id, u.username
-        FROM playlists p, soundcloudusers u
-        WHERE playlist_id = ANY($1) AND p.sc_user_id = u.sc_user_id
-    ", &[&user.playlist_ids])?;
+        SELECT scraped_at, playback_count, likes_count
+        FROM track_stats_snapshots
+        WHERE track_id = $1
+        ORDER BY scraped_at
+    ", &[&id])?;
 
-    Ok(Json(result.into_iter().map(|r| PlaylistInfoBrief {
-        playlist_id: r.get(0),
-        length_ms: r.get(1),
-        created_at: r.get(2),
-        title: r.get(3),
-        is_album: r.get(4),
-        num_tracks: r.get(5),
-        sc_user_id: r.get(6),
-        username: r.get(7)
+    Ok(Json(result.into_iter().map(|r| TrackStatsSnapshot {
+        scraped_at: r.get(0),
+        playback_count: r.get(1),
+        likes_count: r.get(2)
     }).collect()))
 }
 
-/// Get detailed information for a specific playlist
-#[get("/playlist-info/<id>")]
-fn playlist_info(_user: User, db: State<DbClient>, id: i64) -> Result<Json<PlaylistInfoLong>, Error> {
-    let mut conn = db.lock().unwrap();
-    let r = conn.query_one("
-        SELECT p.playlist_id, p.length_ms, p.created_at, p.title, p.is_album,
-            p.num_tracks, u.sc_user_id, u.username, p.track_ids,
-            p.permalink_url, p.description, p.likes_count, u.avatar_url,
-            u.full_name, u.permalink_url
-        FROM playlists p, soundcloudusers u
-        WHERE playlist_id = $1 AND p.sc_user_id = u.sc_user_id
-    ", &[&id])?;
+/// Get a list of all the logged-in user's liked and owned playlists
+#[get("/liked-and-owned-playlists")]
+fn liked_and_owned_playlists(user: User, db: State<DbPool>) -> Result<Json<Vec<PlaylistInfoBrief>>, Error> {
+    let mut conn = db.get()?;
+    let result = conn.query(format!("
+        SELECT {}
+        FROM user_playlists up, playlists, soundcloudusers
+        WHERE up.user_id = $1 AND up.playlist_id = playlists.playlist_id
+            AND playlists.sc_user_id = soundcloudusers.sc_user_id
+    ", PLAYLIST_INFO_BRIEF_COLUMNS).as_str(), &[&user.user_id])?;
+
+    Ok(Json(result.iter().map(playlist_info_brief).collect()))
+}
 
-    Ok(Json(PlaylistInfoLong {
+/// The columns that `playlist_info_long` reads, in order
+///
+/// Queries selecting these need to have the `playlists` and `soundcloudusers`
+/// tables in scope as `p` and `u`.
+const PLAYLIST_INFO_LONG_COLUMNS: &str = "
+    p.playlist_id, p.length_ms, p.created_at, p.title, p.is_album,
+    p.num_tracks, u.sc_user_id, u.username,
+    ARRAY(
+        SELECT track_id FROM playlist_tracks
+        WHERE playlist_id = p.playlist_id
+        ORDER BY position
+    ),
+    p.permalink_url, p.description, p.likes_count, u.avatar_url,
+    u.full_name, u.permalink_url, p.last_refreshed_at,
+    (SELECT hash FROM assets WHERE url = u.avatar_url)";
+
+/// The number of columns in `PLAYLIST_INFO_LONG_COLUMNS`
+const PLAYLIST_INFO_LONG_LEN: usize = 17;
+
+/// Builds a `PlaylistInfoLong` from a row that starts with the columns in
+/// `PLAYLIST_INFO_LONG_COLUMNS`
+fn playlist_info_long(r: &Row) -> PlaylistInfoLong {
+    PlaylistInfoLong {
         brief_info: PlaylistInfoBrief {
             playlist_id: r.get(0),
             length_ms: r.get(1),
@@ -421,37 +1045,339 @@ fn playlist_info(_user: User, db: State<DbClient>, id: i64) -> Result<Json<Playl
         likes_count: r.get(11),
         avatar_url: r.get(12),
         full_name: r.get(13),
-        user_permalink_url: r.get(14)
+        user_permalink_url: r.get(14),
+        last_refreshed_at: r.get(15),
+        local_avatar_url: r.get::<_, Option<String>>(16).map(|h| local_asset_url(&h))
+    }
+}
+
+/// Get detailed information for a specific playlist
+#[get("/playlist-info/<id>")]
+fn playlist_info(_user: User, db: State<DbPool>, id: i64) -> Result<Json<PlaylistInfoLong>, Error> {
+    let mut conn = db.get()?;
+    let r = conn.query_one(format!("
+        SELECT {}
+        FROM playlists p, soundcloudusers u
+        WHERE playlist_id = $1 AND p.sc_user_id = u.sc_user_id
+    ", PLAYLIST_INFO_LONG_COLUMNS).as_str(), &[&id])?;
+
+    Ok(Json(playlist_info_long(&r)))
+}
+
+/// Get the tracks of a specific playlist in order
+///
+/// Tracks that SoundCloud listed in the playlist but that we don't have stored
+/// are included with a `track` of `null`.
+///
+/// Responds with a 404 Not Found and `PlaylistNotFound` if the playlist isn't
+/// stored.
+#[get("/playlist-info/<id>/tracks")]
+fn playlist_tracks(_user: User, db: State<DbPool>, id: i64) -> Result<Json<Vec<PlaylistTrack>>, Error> {
+    let mut conn = db.get()?;
+    if conn.query("SELECT 1 FROM playlists WHERE playlist_id = $1", &[&id])?.is_empty() {
+        return Err(Error::PlaylistNotFound);
+    }
+
+    let result = conn.query(format!("
+        SELECT {}, pt.position, pt.track_id
+        FROM playlist_tracks pt
+        LEFT JOIN tracks ON tracks.track_id = pt.track_id
+        LEFT JOIN soundcloudusers ON soundcloudusers.sc_user_id = tracks.sc_user_id
+        WHERE pt.playlist_id = $1
+        ORDER BY pt.position
+    ", TRACK_INFO_BRIEF_COLUMNS).as_str(), &[&id])?;
+
+    Ok(Json(result.iter().map(|r| {
+        let stored_track_id: Option<i64> = r.get(0);
+
+        PlaylistTrack {
+            position: r.get(TRACK_INFO_BRIEF_LEN),
+            track_id: r.get(TRACK_INFO_BRIEF_LEN + 1),
+            track: stored_track_id.map(|_| track_info_brief(r))
+        }
+    }).collect()))
+}
+
+/// Get an image that was mirrored from SoundCloud
+///
+/// The URLs of mirrored images are included next to the SoundCloud URLs of
+/// artwork and avatars in the responses of other routes.
+#[get("/assets/<hash>")]
+fn asset(
+    _user: User,
+    db: State<DbPool>,
+    store: State<SharedBlobStore>,
+    range: RangeHeader,
+    hash: String
+) -> Result<RangedBlob, Error> {
+    // Hashes come straight from the URL, so make sure they can't point
+    // anywhere outside of the blob store
+    if !is_blob_hash(&hash) {
+        return Err(Error::AssetNotFound);
+    }
+
+    let asset = {
+        let mut conn = db.get()?;
+        Asset::load_hash(&mut conn, &hash)?.ok_or(Error::AssetNotFound)?
+    };
+
+    Ok(RangedBlob {
+        blob: store.open(&asset.hash)?,
+        content_type: asset.content_type.parse().unwrap_or(ContentType::Binary),
+        range: range.0,
+        download_name: None
+    })
+}
+
+/// Get the like and track counts of a specific playlist over time
+///
+/// A snapshot is taken every time the playlist is scraped. Snapshots are
+/// listed oldest first.
+#[get("/playlist-info/<id>/history")]
+fn playlist_history(_user: User, db: State<DbPool>, id: i64) -> Result<Json<Vec<PlaylistStatsSnapshot>>, Error> {
+    let mut conn = db.get()?;
+    let result = conn.query("
+        SELECT scraped_at, likes_count, num_tracks
+        FROM playlist_stats_snapshots
+        WHERE playlist_id = $1
+        ORDER BY scraped_at
+    ", &[&id])?;
+
+    Ok(Json(result.into_iter().map(|r| PlaylistStatsSnapshot {
+        scraped_at: r.get(0),
+        likes_count: r.get(1),
+        num_tracks: r.get(2)
+    }).collect()))
+}
+
+/// The artists in a user's archive, for use in a `WITH` clause
+///
+/// That's the artists of the tracks the user likes or used to like and of the
+/// tracks in the user's playlists, and the makers of those playlists. The
+/// user's id has to be the first query parameter.
+const ARCHIVED_ARTISTS: &str = "
+    archived_artists AS (
+        SELECT tracks.sc_user_id
+        FROM tracks
+        WHERE tracks.track_id IN (SELECT track_id FROM user_likes WHERE user_id = $1)
+            OR tracks.track_id IN (
+                SELECT pt.track_id
+                FROM playlist_tracks pt, user_playlists up
+                WHERE up.user_id = $1 AND pt.playlist_id = up.playlist_id
+            )
+        UNION
+        SELECT playlists.sc_user_id
+        FROM playlists, user_playlists up
+        WHERE up.user_id = $1 AND up.playlist_id = playlists.playlist_id
+    )";
+
+/// Get a list of the artists in the logged-in user's archive
+///
+/// Every SoundCloud user who made a track or playlist in the archive is
+/// listed along with how many of their tracks the user currently likes. The
+/// artists with the most liked tracks come first.
+#[get("/artists")]
+fn artists(user: User, db: State<DbPool>) -> Result<Json<Vec<ArtistSummary>>, Error> {
+    let mut conn = db.get()?;
+    let result = conn.query(format!("
+        WITH {}
+        SELECT {},
+            (
+                SELECT COUNT(*)
+                FROM user_likes l, tracks
+                WHERE l.user_id = $1 AND l.removed_at IS NULL
+                    AND l.track_id = tracks.track_id
+                    AND tracks.sc_user_id = soundcloudusers.sc_user_id
+            ) AS num_liked_tracks
+        FROM soundcloudusers
+        WHERE soundcloudusers.sc_user_id IN (SELECT sc_user_id FROM archived_artists)
+        ORDER BY num_liked_tracks DESC, soundcloudusers.username
+    ", ARCHIVED_ARTISTS, SC_USER_INFO_COLUMNS).as_str(), &[&user.user_id])?;
+
+    Ok(Json(result.iter().map(|r| ArtistSummary {
+        info: sc_user_info(r),
+        num_liked_tracks: r.get(SC_USER_INFO_LEN)
+    }).collect()))
+}
+
+/// Get an artist's profile along with their tracks and playlists in the
+/// logged-in user's archive
+///
+/// The tracks are the artist's tracks that the user currently likes, most
+/// recently liked first. The playlists are the artist's playlists that the
+/// user made or liked.
+///
+/// Responds with a 404 Not Found and `ArtistNotFound` if the artist isn't in
+/// the user's archive.
+#[get("/artists/<sc_user_id>")]
+fn artist(user: User, db: State<DbPool>, sc_user_id: i64) -> Result<Json<ArtistDetail>, Error> {
+    let mut conn = db.get()?;
+    let archived = conn.query(format!("
+        WITH {}
+        SELECT 1 FROM archived_artists WHERE sc_user_id = $2
+    ", ARCHIVED_ARTISTS).as_str(), &[&user.user_id, &sc_user_id])?;
+
+    if archived.is_empty() {
+        return Err(Error::ArtistNotFound);
+    }
+
+    let liked_tracks = conn.query(format!("
+        SELECT {}
+        FROM user_likes l, tracks, soundcloudusers
+        WHERE l.user_id = $1 AND l.removed_at IS NULL AND l.track_id = tracks.track_id
+            AND tracks.sc_user_id = $2 AND tracks.sc_user_id = soundcloudusers.sc_user_id
+        ORDER BY l.liked_at DESC NULLS LAST, l.position
+    ", TRACK_INFO_BRIEF_COLUMNS).as_str(), &[&user.user_id, &sc_user_id])?;
+
+    let playlists = conn.query(format!("
+        SELECT {}
+        FROM user_playlists up, playlists, soundcloudusers
+        WHERE up.user_id = $1 AND up.playlist_id = playlists.playlist_id
+            AND playlists.sc_user_id = $2 AND playlists.sc_user_id = soundcloudusers.sc_user_id
+        ORDER BY playlists.created_at DESC
+    ", PLAYLIST_INFO_BRIEF_COLUMNS).as_str(), &[&user.user_id, &sc_user_id])?;
+
+    Ok(Json(ArtistDetail {
+        info: load_sc_user_info(&mut conn, sc_user_id)?,
+        liked_tracks: liked_tracks.iter().map(track_info_brief).collect(),
+        playlists: playlists.iter().map(playlist_info_brief).collect()
     }))
 }
 
+/// The full-text search document of a track or playlist
+///
+/// This has to match the expression the search indexes were created with.
+const TITLE_DESCRIPTION_SEARCH_VECTOR: &str =
+    "setweight(to_tsvector('english', title), 'A') || setweight(to_tsvector('english', description), 'B')";
+/// The full-text search document of a SoundCloud user
+///
+/// This has to match the expression the search index was created with.
+const ARTIST_SEARCH_VECTOR: &str = "to_tsvector('simple', username || ' ' || full_name)";
+/// How many results `/search` returns unless asked otherwise
+const DEFAULT_SEARCH_LIMIT: i64 = 50;
+/// The most results `/search` will return at once
+const MAX_SEARCH_LIMIT: i64 = 500;
+
+/// Search the logged-in user's archive
+///
+/// The words in `q` are looked for in the titles and descriptions of the
+/// tracks and playlists in the user's archive, and in the names of the artists
+/// behind them. Results of every kind are listed together, best matches first.
+///
+/// At most `limit` results are listed (50 by default, 500 at most).
+#[get("/search?<q>&<limit>")]
+fn search(
+    user: User,
+    db: State<DbPool>,
+    q: String,
+    limit: Option<i64>
+) -> Result<Json<Vec<SearchResult>>, Error> {
+    if q.trim().is_empty() {
+        return Err(Error::InvalidParameter("q".into()));
+    }
+
+    let limit = limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    if limit < 1 || limit > MAX_SEARCH_LIMIT {
+        return Err(Error::InvalidParameter("limit".into()));
+    }
+
+    let mut conn = db.get()?;
+    let result = conn.query(format!("
+        WITH archived_tracks AS (
+            SELECT track_id FROM user_likes WHERE user_id = $1
+            UNION
+            SELECT pt.track_id
+            FROM playlist_tracks pt, user_playlists up
+            WHERE up.user_id = $1 AND pt.playlist_id = up.playlist_id
+        ), archived_playlists AS (
+            SELECT playlist_id FROM user_playlists WHERE user_id = $1
+        )
+        SELECT
+            '{track}',
+            track_id,
+            title,
+            ts_headline('english', title || ' ' || description, plainto_tsquery('english', $2)),
+            ts_rank({document}, plainto_tsquery('english', $2)) AS rank
+        FROM tracks
+        WHERE track_id IN (SELECT track_id FROM archived_tracks)
+            AND {document} @@ plainto_tsquery('english', $2)
+        UNION ALL
+        SELECT
+            '{playlist}',
+            playlist_id,
+            title,
+            ts_headline('english', title || ' ' || description, plainto_tsquery('english', $2)),
+            ts_rank({document}, plainto_tsquery('english', $2))
+        FROM playlists
+        WHERE playlist_id IN (SELECT playlist_id FROM archived_playlists)
+            AND {document} @@ plainto_tsquery('english', $2)
+        UNION ALL
+        SELECT
+            '{artist}',
+            sc_user_id,
+            username,
+            ts_headline('simple', username || ' ' || full_name, plainto_tsquery('simple', $2)),
+            ts_rank({artist_document}, plainto_tsquery('simple', $2))
+        FROM soundcloudusers
+        WHERE sc_user_id IN (
+                SELECT sc_user_id FROM tracks WHERE track_id IN (SELECT track_id FROM archived_tracks)
+                UNION
+                SELECT sc_user_id FROM playlists WHERE playlist_id IN (SELECT playlist_id FROM archived_playlists)
+            )
+            AND {artist_document} @@ plainto_tsquery('simple', $2)
+        ORDER BY rank DESC, 1, 2
+        LIMIT $3
+    ",
+        track = SearchResultKind::Track.as_str(),
+        playlist = SearchResultKind::Playlist.as_str(),
+        artist = SearchResultKind::Artist.as_str(),
+        document = TITLE_DESCRIPTION_SEARCH_VECTOR,
+        artist_document = ARTIST_SEARCH_VECTOR
+    ).as_str(), &[&user.user_id, &q, &limit])?;
+
+    Ok(Json(result.iter().map(|r| {
+        let kind: String = r.get(0);
+
+        SearchResult {
+            kind: kind.parse().expect("invalid search result kind"),
+            id: r.get(1),
+            name: r.get(2),
+            snippet: r.get(3),
+            rank: r.get(4)
+        }
+    }).collect()))
+}
+
 /// Clear the logged in user's liked tracks
 /// 
-/// This does not delete the liked tracks from the database. It clears the list
-/// of liked track IDs in the users table.
+/// This does not delete the liked tracks from the database. It forgets which
+/// tracks the user currently likes, but keeps the likes that were already
+/// removed in the user's like history.
 #[get("/clear-liked-tracks")]
-fn clear_liked_tracks(user: User, db: State<DbClient>) -> Result<(), Error> {
-    let mut conn = db.lock().unwrap();
-    user.update_liked_track_ids(&mut conn, vec![])
+fn clear_liked_tracks(user: User, db: State<DbPool>) -> Result<(), Error> {
+    let mut conn = db.get()?;
+    user.clear_likes(&mut conn)
 }
 
 /// Clear the logged in user's playlists
 /// 
-/// This does not delete playlists or tracks from the database. It clears the list
-/// of playlist IDs in the users table.
+/// This does not delete playlists or tracks from the database. It forgets which
+/// playlists the user has made or liked.
 #[get("/clear-playlists")]
-fn clear_playlists(user: User, db: State<DbClient>) -> Result<(), Error> {
-    let mut conn = db.lock().unwrap();
-    user.update_playlist_ids(&mut conn, vec![])
+fn clear_playlists(user: User, db: State<DbPool>) -> Result<(), Error> {
+    let mut conn = db.get()?;
+    user.clear_playlists(&mut conn)
 }
 
 /// Get the logged in user's most liked artist
 #[get("/most-liked-artist")]
-fn most_liked_artist(user: User, db: State<DbClient>) -> Result<Json<ScUserInfo>, Error> {
-    let mut conn = db.lock().unwrap();
+fn most_liked_artist(user: User, db: State<DbPool>) -> Result<Json<ScUserInfo>, Error> {
+    let mut conn = db.get()?;
     let mut likes_by_artist = HashMap::new();
 
-    if user.liked_track_ids.len() < 1 {
+    let liked_track_ids = user.liked_track_ids(&mut *conn)?;
+
+    if liked_track_ids.len() < 1 {
         return Err(Error::NoLikedTracksForUser);
     }
 
@@ -460,7 +1386,7 @@ fn most_liked_artist(user: User, db: State<DbClient>) -> Result<Json<ScUserInfo>
             SELECT sc_user_id
             FROM tracks
             WHERE track_id = ANY($1)
-        ", vec![&user.liked_track_ids as _])?;
+        ", vec![&liked_track_ids as _])?;
     
         while let Some(r) = result_iter.next()? {
             let id: i64 = r.get(0);
@@ -479,24 +1405,19 @@ fn most_liked_artist(user: User, db: State<DbClient>) -> Result<Json<ScUserInfo>
         }
     }
 
-    let sc_u = SoundCloudUser::load_id(&mut conn, most_liked_id)?;
-    Ok(Json(ScUserInfo {
-        sc_user_id: sc_u.sc_user_id,
-        avatar_url: sc_u.avatar_url,
-        full_name: sc_u.full_name,
-        username: sc_u.username,
-        permalink_url: sc_u.permalink_url
-    }))
+    Ok(Json(load_sc_user_info(&mut conn, most_liked_id)?))
 }
 
 /// Get the logged in user's average playback count across all of their likes
 /// 
 /// Note that this route just responds with a single numerical value in the JSON.
 #[get("/average-playback-count")]
-fn average_playback_count(user: User, db: State<DbClient>) -> Result<Json<i64>, Error> {
-    let mut conn = db.lock().unwrap();
+fn average_playback_count(user: User, db: State<DbPool>) -> Result<Json<i64>, Error> {
+    let mut conn = db.get()?;
 
-    if user.liked_track_ids.len() < 1 {
+    let liked_track_ids = user.liked_track_ids(&mut *conn)?;
+
+    if liked_track_ids.len() < 1 {
         return Err(Error::NoLikedTracksForUser);
     }
 
@@ -504,7 +1425,7 @@ fn average_playback_count(user: User, db: State<DbClient>) -> Result<Json<i64>,
         SELECT playback_count
         FROM tracks
         WHERE track_id = ANY($1)
-    ", vec![&user.liked_track_ids as _])?;
+    ", vec![&liked_track_ids as _])?;
 
     Ok(Json(
         result_iter
@@ -519,8 +1440,8 @@ fn average_playback_count(user: User, db: State<DbClient>) -> Result<Json<i64>,
     ))
 }
 
-/// Create a Rocket instance given a PostgreSQL client.
-fn rocket(client: Client) -> Result<rocket::Rocket, Error> {
+/// Create a Rocket instance given a PostgreSQL connection pool.
+fn rocket(db: DbPool, store: SharedBlobStore) -> Result<rocket::Rocket, Error> {
     #[cfg(feature = "deployable")]
     let static_files_dir = root_dir!().join("static");
     #[cfg(not(feature = "deployable"))]
@@ -528,17 +1449,40 @@ fn rocket(client: Client) -> Result<rocket::Rocket, Error> {
 
     Ok(
         rocket::ignite()
-            .manage(Arc::new(Mutex::new(client)))
+            .manage(ScrapeWorker::new(db.clone(), store.clone()))
+            .manage(db)
+            .manage(store)
             .manage(ArgonSecretKey(env::var("ARGON_SECRET_KEY").unwrap()))
             .mount("/", StaticFiles::from(static_files_dir))
             .mount("/api", routes![
                 set_auth_creds,
                 sse_auth_token,
                 do_scraping,
+                scrape_jobs,
+                scrape_job,
+                cancel_scrape_job,
+                verify_tracks,
+                archive_audio,
+                track_audio,
+                start_export,
+                export_bundle,
+                import,
+                scrape_schedule,
+                set_scrape_schedule,
                 liked_tracks,
+                like_history,
+                lost_tracks,
                 track_info,
+                track_info_batch,
+                track_history,
+                asset,
                 liked_and_owned_playlists,
                 playlist_info,
+                playlist_tracks,
+                playlist_history,
+                search,
+                artists,
+                artist,
                 clear_liked_tracks,
                 clear_playlists,
                 register,
@@ -559,12 +1503,62 @@ fn rocket(client: Client) -> Result<rocket::Rocket, Error> {
     )
 }
 
+/// Checks the blob store for blobs that are corrupt or missing, exiting with a
+/// failure status if any are found.
+fn verify_blobs_command() -> Result<(), Error> {
+    let report = verify_blobs(&postgresql_pool()?, &*blob_store_from_env()?)?;
+
+    for hash in &report.corrupt {
+        println!("corrupt: {}", hash);
+    }
+    for hash in &report.missing {
+        println!("missing: {}", hash);
+    }
+
+    if !report.corrupt.is_empty() || !report.missing.is_empty() {
+        std::process::exit(1);
+    }
+    println!("All blobs are intact");
+
+    Ok(())
+}
+
+/// Deletes the blobs that nothing in the database refers to anymore.
+fn gc_blobs_command() -> Result<(), Error> {
+    let deleted = collect_garbage(
+        &postgresql_pool()?,
+        &*blob_store_from_env()?,
+        chrono::Duration::hours(GC_MIN_BLOB_AGE_HOURS)
+    )?;
+
+    for hash in &deleted {
+        println!("deleted: {}", hash);
+    }
+    println!("Deleted {} unreferenced blobs", deleted.len());
+
+    Ok(())
+}
+
 fn main() -> Result<(), Error> {
     dotenv().ok();
+
+    match env::args().nth(1).as_ref().map(String::as_str) {
+        Some("verify-blobs") => return verify_blobs_command(),
+        Some("gc-blobs") => return gc_blobs_command(),
+        _ => {}
+    }
+
     SSE.spawn("[::1]:3000".parse().unwrap());
 
+    let rocket = rocket(postgresql_pool()?, blob_store_from_env()?)?;
+    let db = rocket.state::<DbPool>().unwrap().clone();
+    let worker = rocket.state::<ScrapeWorker>().unwrap().clone();
+
+    worker.resume_unfinished()?;
+    spawn_scheduler(db, worker);
+
     // Rocket pretty prints the error on drop if one occurs
-    let _ = rocket(postgresql_client()?)?.launch();
+    let _ = rocket.launch();
 
     Ok(())
 }
@@ -0,0 +1,142 @@
+use super::*;
+
+use std::collections::HashSet;
+
+/// A numbered change to the database schema
+struct Migration {
+    /// Migrations are applied in order of their version
+    version: i32,
+    /// A short summary of the change, recorded when it's applied
+    description: &'static str,
+    /// The SQL statements that make the change
+    sql: &'static str
+}
+
+/// Every migration, in order.
+///
+/// A migration must never be edited once it has been released, since databases
+/// that already applied it won't see the edit. Add a new one instead.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "baseline",
+        sql: include_str!("../migrations/0001_baseline.sql")
+    },
+    Migration {
+        version: 2,
+        description: "scrape jobs",
+        sql: include_str!("../migrations/0002_scrape_jobs.sql")
+    },
+    Migration {
+        version: 3,
+        description: "scrape job modes",
+        sql: include_str!("../migrations/0003_scrape_job_mode.sql")
+    },
+    Migration {
+        version: 4,
+        description: "scheduled scraping",
+        sql: include_str!("../migrations/0004_scrape_schedule.sql")
+    },
+    Migration {
+        version: 5,
+        description: "like history",
+        sql: include_str!("../migrations/0005_like_history.sql")
+    },
+    Migration {
+        version: 6,
+        description: "track availability",
+        sql: include_str!("../migrations/0006_track_availability.sql")
+    },
+    Migration {
+        version: 7,
+        description: "metadata refresh times",
+        sql: include_str!("../migrations/0007_last_refreshed_at.sql")
+    },
+    Migration {
+        version: 8,
+        description: "stats snapshots",
+        sql: include_str!("../migrations/0008_stats_snapshots.sql")
+    },
+    Migration {
+        version: 9,
+        description: "join tables for likes and playlists",
+        sql: include_str!("../migrations/0009_join_tables.sql")
+    },
+    Migration {
+        version: 10,
+        description: "store created_at as timestamps",
+        sql: include_str!("../migrations/0010_timestamptz_created_at.sql")
+    },
+    Migration {
+        version: 11,
+        description: "full-text search indexes",
+        sql: include_str!("../migrations/0011_search.sql")
+    },
+    Migration {
+        version: 12,
+        description: "archived track audio",
+        sql: include_str!("../migrations/0012_track_audio.sql")
+    },
+    Migration {
+        version: 13,
+        description: "mirrored artwork and avatars",
+        sql: include_str!("../migrations/0013_assets.sql")
+    },
+    Migration {
+        version: 14,
+        description: "export bundles",
+        sql: include_str!("../migrations/0014_exports.sql")
+    }
+];
+
+/// An arbitrary key for the advisory lock held while migrating, so that two
+/// servers starting up at once don't both try to apply the same migrations
+const MIGRATION_LOCK_KEY: i64 = 0x6d69_6772_6174_65;
+
+/// Brings the given database's schema up to date by applying every migration
+/// that hasn't been applied to it yet.
+///
+/// Applied migrations are recorded in the `schema_migrations` table. All
+/// pending migrations are applied in one transaction, so a failing migration
+/// leaves the schema as it was.
+///
+/// Databases that were created before migrations existed have tables but no
+/// `schema_migrations` table. The baseline migration only creates what is
+/// missing, so applying it to such a database adopts it as the baseline, and
+/// the later migrations bring it up to date from there.
+pub fn run_migrations(client: &mut Client) -> Result<(), Error> {
+    let mut transaction = client.transaction()?;
+    transaction.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_KEY])?;
+
+    let predates_migrations: bool = transaction.query_one(
+        "SELECT to_regclass('schema_migrations') IS NULL AND to_regclass('users') IS NOT NULL",
+        &[]
+    )?.get(0);
+    if predates_migrations {
+        println!("Found a database from before schema migrations; adopting it as the baseline");
+    }
+
+    transaction.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version         INTEGER PRIMARY KEY,
+            description     TEXT NOT NULL,
+            applied_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+        )"
+    )?;
+
+    let applied: HashSet<i32> = transaction
+        .query("SELECT version FROM schema_migrations", &[])?
+        .iter()
+        .map(|r| r.get(0))
+        .collect();
+
+    for migration in MIGRATIONS.iter().filter(|m| !applied.contains(&m.version)) {
+        transaction.batch_execute(migration.sql)?;
+        transaction.execute(
+            "INSERT INTO schema_migrations (version, description) VALUES ($1, $2)",
+            &[&migration.version, &migration.description]
+        )?;
+    }
+
+    Ok(transaction.commit()?)
+}
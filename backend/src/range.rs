@@ -0,0 +1,175 @@
+use rocket::{request::{self, FromRequest}, Request, Outcome};
+use rocket::response::{self, Response};
+use rocket::http::ContentType;
+
+use super::*;
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// The `Range` header of a request, if it had one
+pub struct RangeHeader(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for RangeHeader {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(RangeHeader(request.headers().get_one("Range").map(String::from)))
+    }
+}
+
+/// What part of a file a `Range` header asks for
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    /// The whole file
+    Full,
+    /// The bytes from `start` up to and including `end`
+    Partial { start: u64, end: u64 },
+    /// A range that lies entirely outside of the file
+    Unsatisfiable
+}
+
+/// Works out which bytes of a file that is `len` bytes long the given `Range`
+/// header asks for.
+///
+/// Only single ranges of bytes are supported. Headers asking for anything else,
+/// and headers that can't be parsed, are ignored by answering with the full
+/// file, which is allowed by RFC 7233.
+pub fn parse_range(header: &str, len: u64) -> ByteRange {
+    let header = header.trim();
+    if !header.starts_with("bytes=") || header.contains(',') {
+        return ByteRange::Full;
+    }
+    let spec = header["bytes=".len()..].trim();
+    let dash = match spec.find('-') {
+        Some(dash) => dash,
+        None => return ByteRange::Full
+    };
+    let (start, end) = (&spec[..dash], &spec[dash + 1..]);
+
+    if start.is_empty() {
+        // A suffix range: the last `end` bytes of the file
+        return match end.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial { start: len.saturating_sub(suffix), end: len - 1 },
+            Err(_) => ByteRange::Full
+        };
+    }
+
+    let start: u64 = match start.parse() {
+        Ok(start) => start,
+        Err(_) => return ByteRange::Full
+    };
+    let end: u64 = if end.is_empty() {
+        std::u64::MAX
+    } else {
+        match end.parse() {
+            Ok(end) if end >= start => end,
+            _ => return ByteRange::Full
+        }
+    };
+
+    if start >= len {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial { start, end: end.min(len - 1) }
+    }
+}
+
+/// A blob served with support for `Range` requests
+pub struct RangedBlob {
+    pub blob: Box<dyn BlobReader>,
+    pub content_type: ContentType,
+    /// The `Range` header of the request being responded to
+    pub range: Option<String>,
+    /// The file name browsers should save the blob under, if it should be
+    /// downloaded rather than shown
+    pub download_name: Option<String>
+}
+
+impl<'r> Responder<'r> for RangedBlob {
+    fn respond_to(mut self, _: &Request) -> response::Result<'r> {
+        let len = self.blob
+            .seek(SeekFrom::End(0))
+            .and_then(|len| self.blob.seek(SeekFrom::Start(0)).map(|_| len))
+            .map_err(|_| Status::InternalServerError)?;
+        let range = self.range.as_ref().map(|r| parse_range(r, len)).unwrap_or(ByteRange::Full);
+
+        let mut response = Response::build();
+        response
+            .header(self.content_type)
+            .raw_header("Accept-Ranges", "bytes");
+        if let Some(name) = &self.download_name {
+            response.raw_header("Content-Disposition", format!("attachment; filename=\"{}\"", name));
+        }
+
+        match range {
+            ByteRange::Full => {
+                response.sized_body(self.blob);
+            },
+            ByteRange::Partial { start, end } => {
+                let slice = Slice::new(self.blob, start, end - start + 1)
+                    .map_err(|_| Status::InternalServerError)?;
+
+                response
+                    .status(Status::PartialContent)
+                    .raw_header("Content-Range", format!("bytes {}-{}/{}", start, end, len))
+                    .sized_body(slice);
+            },
+            ByteRange::Unsatisfiable => {
+                response
+                    .status(Status::RangeNotSatisfiable)
+                    .raw_header("Content-Range", format!("bytes */{}", len));
+            }
+        }
+
+        response.ok()
+    }
+}
+
+/// A window into a blob that reads and seeks as if it were a blob of its own
+struct Slice<R> {
+    inner: R,
+    /// Where in the blob the window starts
+    start: u64,
+    /// How long the window is
+    len: u64,
+    /// Where in the window we are
+    pos: u64
+}
+
+impl<R: Seek> Slice<R> {
+    fn new(mut inner: R, start: u64, len: u64) -> io::Result<Self> {
+        inner.seek(SeekFrom::Start(start))?;
+        Ok(Self { inner, start, len, pos: 0 })
+    }
+}
+
+impl<R: Read> Read for Slice<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.pos);
+        let max = (buf.len() as u64).min(remaining) as usize;
+        let n = self.inner.read(&mut buf[..max])?;
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Slice<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(n) => self.len as i64 + n,
+            SeekFrom::Current(n) => self.pos as i64 + n
+        };
+        if pos < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of slice"));
+        }
+
+        self.pos = pos as u64;
+        self.inner.seek(SeekFrom::Start(self.start + self.pos))?;
+
+        Ok(self.pos)
+    }
+}
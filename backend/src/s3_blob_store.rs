@@ -0,0 +1,386 @@
+use super::*;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Where the bucket is assumed to be unless `S3_REGION` says otherwise
+const DEFAULT_S3_REGION: &str = "us-east-1";
+
+/// The headers that are included in every request signature
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+/// The hex-encoded SHA-256 hash of an empty request body
+const EMPTY_PAYLOAD_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// A blob store that keeps its blobs in a bucket of an S3-compatible object
+/// storage service, such as AWS S3 or MinIO.
+///
+/// Objects are addressed path-style (`<endpoint>/<bucket>/<key>`) and requests
+/// are signed with AWS Signature Version 4. Blobs are keyed the same way they
+/// are laid out on disk by `FsBlobStore`.
+#[derive(Clone)]
+pub struct S3BlobStore {
+    /// The URL of the service, without a trailing slash
+    endpoint: String,
+    /// The host (and port, if there is one) part of `endpoint`
+    host: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String
+}
+
+impl S3BlobStore {
+    /// Creates a store that keeps its blobs in the given bucket of the service
+    /// at the given endpoint (such as `http://localhost:9000`).
+    pub fn new(
+        endpoint: &str,
+        bucket: &str,
+        region: &str,
+        access_key: &str,
+        secret_key: &str
+    ) -> Result<Self, Error> {
+        let endpoint = endpoint.trim_end_matches('/');
+        let host = ["https://", "http://"]
+            .iter()
+            .find(|scheme| endpoint.starts_with(*scheme))
+            .map(|scheme| &endpoint[scheme.len()..])
+            .ok_or_else(|| Error::BlobStoreError(format!("S3 endpoint {} has no http(s) scheme", endpoint)))?;
+        if host.is_empty() || host.contains('/') {
+            return Err(Error::BlobStoreError(format!("S3 endpoint {} must not have a path", endpoint)));
+        }
+
+        Ok(Self {
+            endpoint: endpoint.into(),
+            host: host.into(),
+            bucket: bucket.into(),
+            region: region.into(),
+            access_key: access_key.into(),
+            secret_key: secret_key.into()
+        })
+    }
+
+    /// Creates a store from the `S3_ENDPOINT`, `S3_BUCKET`, `S3_ACCESS_KEY`,
+    /// `S3_SECRET_KEY` and (optionally) `S3_REGION` environment variables.
+    pub fn from_env() -> Result<Self, Error> {
+        let var = |name: &str| env::var(name)
+            .map_err(|_| Error::BlobStoreError(format!("{} must be set to use an S3 blob store", name)));
+
+        Self::new(
+            &var("S3_ENDPOINT")?,
+            &var("S3_BUCKET")?,
+            &env::var("S3_REGION").unwrap_or_else(|_| DEFAULT_S3_REGION.into()),
+            &var("S3_ACCESS_KEY")?,
+            &var("S3_SECRET_KEY")?
+        )
+    }
+
+    /// Builds a signed request for the given object (or for the bucket itself
+    /// if `key` is `None`).
+    ///
+    /// `payload_hash` is the hex-encoded SHA-256 hash of the body that will be
+    /// sent with the request.
+    fn request(
+        &self,
+        method: &str,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        payload_hash: &str
+    ) -> ureq::Request {
+        let now = Utc::now();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+
+        let path = match key {
+            Some(key) => format!("/{}/{}", self.bucket, key),
+            None => format!("/{}", self.bucket)
+        };
+        let mut query: Vec<_> = query.iter().map(|(k, v)| (uri_encode(k), uri_encode(v))).collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, query, self.host, payload_hash, timestamp, SIGNED_HEADERS, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let signing_key = [self.region.as_str(), "s3", "aws4_request"]
+            .iter()
+            .fold(
+                hmac_sha256(format!("AWS4{}", self.secret_key).as_bytes(), date.as_bytes()),
+                |key, part| hmac_sha256(&key, part.as_bytes())
+            );
+        let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+        let url = if query.is_empty() {
+            format!("{}{}", self.endpoint, path)
+        } else {
+            format!("{}{}?{}", self.endpoint, path, query)
+        };
+        let mut request = ureq::request(method, &url);
+        request
+            .set("x-amz-date", &timestamp)
+            .set("x-amz-content-sha256", payload_hash)
+            .set("Authorization", &format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.access_key, scope, SIGNED_HEADERS, signature
+            ));
+
+        request
+    }
+
+    /// Starts reading the object with the given key from the given byte
+    /// onwards.
+    fn get_from(&self, key: &str, start: u64) -> Result<ureq::Response, Error> {
+        let response = self.request("GET", Some(key), &[], EMPTY_PAYLOAD_HASH)
+            .set("Range", &format!("bytes={}-", start))
+            .call();
+
+        expect_success(response, "GET", key)
+    }
+}
+
+impl BlobStore for S3BlobStore {
+    fn put(&self, reader: &mut dyn Read) -> Result<StoredBlob, Error> {
+        // The size and hash of the body have to be known before an upload
+        // starts, so the blob is written to a temporary file first
+        let spool_path = env::temp_dir().join(format!("crate-s3-upload-{}", temp_file_name()));
+
+        let uploaded = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&spool_path)
+            .map_err(Error::from)
+            .and_then(|mut file| {
+                let (hash, size_bytes) = copy_hashed(reader, &mut file)?;
+                file.seek(SeekFrom::Start(0))?;
+
+                // The service checks the body against the hash it's signed
+                // with, so a blob can't be stored with the wrong contents
+                let path = blob_path(&hash);
+                let response = self.request("PUT", Some(&path), &[], &hash)
+                    .set("Content-Length", &size_bytes.to_string())
+                    .send(file);
+                expect_success(response, "PUT", &path)?;
+
+                Ok(StoredBlob {
+                    hash,
+                    path,
+                    size_bytes
+                })
+            });
+
+        let _ = fs::remove_file(&spool_path);
+        uploaded
+    }
+
+    fn open(&self, hash: &str) -> Result<Box<dyn BlobReader>, Error> {
+        let key = blob_path(hash);
+        let response = self.request("HEAD", Some(&key), &[], EMPTY_PAYLOAD_HASH).call();
+        if response.status() == 404 {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("no blob {}", hash)).into());
+        }
+        let response = expect_success(response, "HEAD", &key)?;
+
+        let len = response
+            .header("Content-Length")
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(|| Error::BlobStoreError(format!("HEAD {} had no Content-Length", key)))?;
+
+        Ok(Box::new(S3Object {
+            store: self.clone(),
+            key,
+            len,
+            pos: 0,
+            body: None
+        }))
+    }
+
+    fn delete(&self, hash: &str) -> Result<(), Error> {
+        let key = blob_path(hash);
+        let response = self.request("DELETE", Some(&key), &[], EMPTY_PAYLOAD_HASH).call();
+        if response.status() == 404 {
+            return Ok(());
+        }
+
+        expect_success(response, "DELETE", &key).map(|_| ())
+    }
+
+    fn list(&self) -> Result<Vec<BlobInfo>, Error> {
+        let mut blobs = vec![];
+        let mut continuation_token = None;
+
+        loop {
+            let mut query = vec![("list-type", "2")];
+            if let Some(token) = &continuation_token {
+                query.push(("continuation-token", token.as_str()));
+            }
+
+            let response = self.request("GET", None, &query, EMPTY_PAYLOAD_HASH).call();
+            let body = expect_success(response, "GET", "?list-type=2")?.into_string()?;
+
+            for object in xml_elements(&body, "Contents") {
+                let key = xml_elements(object, "Key").into_iter().next().unwrap_or("");
+                let hash = key.rsplit('/').next().unwrap_or("");
+                // Skips anything in the bucket that isn't a blob
+                if !is_blob_hash(hash) || key != blob_path(hash) {
+                    continue;
+                }
+
+                let last_modified = xml_elements(object, "LastModified").into_iter().next().unwrap_or("");
+                blobs.push(BlobInfo {
+                    hash: hash.into(),
+                    last_modified: DateTime::parse_from_rfc3339(last_modified)
+                        .map_err(|_| Error::BlobStoreError(format!("{} has malformed LastModified {}", key, last_modified)))?
+                        .with_timezone(&Utc)
+                });
+            }
+
+            if xml_elements(&body, "IsTruncated").first() != Some(&"true") {
+                break;
+            }
+            continuation_token = match xml_elements(&body, "NextContinuationToken").first() {
+                Some(token) => Some(xml_unescape(token)),
+                None => break
+            };
+        }
+
+        Ok(blobs)
+    }
+}
+
+/// An object in an S3 bucket that is read lazily with ranged GET requests.
+///
+/// A request is only made once something is read, and seeking elsewhere drops
+/// the request in flight, so seeking to the part of a blob that's wanted
+/// before reading only downloads that part.
+struct S3Object {
+    store: S3BlobStore,
+    key: String,
+    /// How long the object is
+    len: u64,
+    /// Where in the object we are
+    pos: u64,
+    /// The body of the request reading from `pos` onwards, if one was made
+    body: Option<Box<dyn Read + Send>>
+}
+
+impl Read for S3Object {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.body.is_none() {
+            let response = self.store
+                .get_from(&self.key, self.pos)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
+            self.body = Some(Box::new(response.into_reader()));
+        }
+
+        let n = self.body.as_mut().unwrap().read(buf)?;
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl Seek for S3Object {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(n) => self.len as i64 + n,
+            SeekFrom::Current(n) => self.pos as i64 + n
+        };
+        if pos < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of object"));
+        }
+
+        if pos as u64 != self.pos {
+            self.pos = pos as u64;
+            self.body = None;
+        }
+
+        Ok(self.pos)
+    }
+}
+
+/// Errors unless the given response to a request for the given key was
+/// successful.
+fn expect_success(response: ureq::Response, method: &str, key: &str) -> Result<ureq::Response, Error> {
+    if let Some(e) = response.synthetic_error() {
+        return Err(Error::RequestError(e.to_string()));
+    }
+    if !response.ok() {
+        return Err(Error::BlobStoreError(format!(
+            "{} {} failed with status {}",
+            method,
+            key,
+            response.status()
+        )));
+    }
+
+    Ok(response)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC can take keys of any size");
+    mac.input(data);
+    mac.result().code().to_vec()
+}
+
+/// Percent-encodes everything but the characters that AWS leaves alone when
+/// signing requests.
+fn uri_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b)
+        })
+        .collect()
+}
+
+/// The contents of every element with the given tag in the given XML.
+///
+/// S3 responses are simple enough that this is all the XML parsing needed.
+fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut elements = vec![];
+    let mut rest = xml;
+
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        match after.find(&close) {
+            Some(end) => {
+                elements.push(&after[..end]);
+                rest = &after[end + close.len()..];
+            },
+            None => break
+        }
+    }
+
+    elements
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
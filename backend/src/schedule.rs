@@ -0,0 +1,54 @@
+use super::*;
+
+use std::time::Duration;
+
+/// How long the scheduler waits between checks for users that are due a scrape
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Starts a thread that periodically enqueues scrapes for users who have
+/// turned on scheduled scraping.
+///
+/// Whether a user is due is decided by the `next_scheduled_scrape_at` column,
+/// so runs that were missed while the server was down are caught up on the
+/// first check after it starts again (which happens right away).
+pub fn spawn_scheduler(db: DbPool, worker: ScrapeWorker) {
+    thread::spawn(move || loop {
+        if let Err(e) = run_due_scrapes(&db, &worker) {
+            eprintln!("Failed to run scheduled scrapes: {:?}", e);
+        }
+
+        thread::sleep(SCHEDULER_INTERVAL);
+    });
+}
+
+/// Enqueues an incremental scrape for every user whose next scheduled scrape
+/// is due.
+///
+/// However many runs a user missed, only one scrape is enqueued for them.
+/// Users that already have a scrape job running are left due, so they are
+/// tried again on the next check.
+/// Users without SoundCloud credentials aren't considered due at all (see
+/// `User::load_due_for_scheduled_scrape`).
+pub fn run_due_scrapes(db: &DbPool, worker: &ScrapeWorker) -> Result<(), Error> {
+    let users = {
+        let mut conn = db.get()?;
+        User::load_due_for_scheduled_scrape(&mut conn)?
+    };
+
+    for mut user in users {
+        match worker.enqueue(&user, ScrapeMode::Incremental, None, None) {
+            Ok(_) => {},
+            Err(Error::ScrapeAlreadyRunning) => continue,
+            Err(e) => eprintln!(
+                "Failed to start scheduled scrape for user {}: {:?}",
+                user.user_id,
+                e
+            )
+        }
+
+        let mut conn = db.get()?;
+        user.schedule_next_scrape(&mut conn)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,441 @@
+use orange_zest::api::likes::Likes;
+use orange_zest::api::playlists::Playlists;
+use orange_zest::Zester;
+
+use super::*;
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How many likes or playlists an incremental scrape asks for at first.
+///
+/// The window doubles every time it turns out to contain nothing that's
+/// already archived.
+const INCREMENTAL_WINDOW: u64 = 50;
+
+/// Creates a `Zester` with the SoundCloud credentials stored for the given user.
+pub fn zester_for(user: &User) -> Result<Zester, Error> {
+    match (user.sc_oauth_token.clone(), user.sc_client_id.clone()) {
+        (Some(oauth_token), Some(client_id)) => Ok(Zester::new(oauth_token, client_id)?),
+        _ => Err(Error::ScAuthTokensNotPresent)
+    }
+}
+
+/// Runs scrape jobs on background threads.
+///
+/// Every job is recorded in the `scrape_jobs` table before it starts, and its
+/// row is kept up to date as the job runs, so the outcome of a scrape can be
+/// looked up long after it finished.
+///
+/// Jobs can be cancelled while they run. Each running job has a flag that it
+/// checks between its scraping phases and whenever it receives a progress
+/// event; once it notices the flag has been set, it stops without storing
+/// anything it fetched.
+#[derive(Clone)]
+pub struct ScrapeWorker {
+    db: DbPool,
+    /// Where audio archiving jobs put the audio they download, and export
+    /// jobs the bundles they make
+    store: SharedBlobStore,
+    /// Cancellation flags for the jobs that are running in this process, by
+    /// job id
+    cancel_flags: Arc<Mutex<HashMap<i32, Arc<AtomicBool>>>>
+}
+
+impl ScrapeWorker {
+    pub fn new(db: DbPool, store: SharedBlobStore) -> Self {
+        Self {
+            db,
+            store,
+            cancel_flags: Arc::new(Mutex::new(HashMap::new()))
+        }
+    }
+
+    /// Records a new job for the given user and starts running it, returning
+    /// the job as it was queued.
+    pub fn enqueue(
+        &self,
+        user: &User,
+        mode: ScrapeMode,
+        num_recent_likes: Option<i64>,
+        num_recent_playlists: Option<i64>
+    ) -> Result<ScrapeJob, Error> {
+        let job = {
+            let mut conn = self.db.get()?;
+            ScrapeJob::create_new(
+                &mut conn,
+                user.user_id,
+                mode,
+                num_recent_likes,
+                num_recent_playlists
+            )?
+        };
+
+        self.spawn(job.clone());
+        Ok(job)
+    }
+
+    /// Picks back up the jobs that were left unfinished when the server last
+    /// stopped.
+    ///
+    /// Queued jobs are started again. Jobs that were running are marked as
+    /// failed, since whatever they had fetched so far was lost.
+    pub fn resume_unfinished(&self) -> Result<(), Error> {
+        let jobs = {
+            let mut conn = self.db.get()?;
+            ScrapeJob::load_unfinished(&mut conn)?
+        };
+
+        for mut job in jobs {
+            if job.status == ScrapeJobStatus::Running {
+                let mut conn = self.db.get()?;
+                job.mark_finished(&mut conn, Some(&Error::ScrapeInterrupted))?;
+            } else {
+                self.spawn(job);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Asks the given job of the given user to stop, returning the job.
+    ///
+    /// A job that isn't being run by this worker (which can only happen to a
+    /// queued job, or one that just finished) is marked as cancelled right
+    /// away, unless it finished in the meantime. Running jobs are marked as
+    /// cancelled by their own thread once they notice the request.
+    pub fn cancel(&self, user: &User, job_id: i32) -> Result<ScrapeJob, Error> {
+        let mut conn = self.db.get()?;
+        let mut job = ScrapeJob::load_id_for_user(&mut conn, job_id, user.user_id)?;
+
+        if job.is_finished() {
+            return Err(Error::ScrapeJobAlreadyFinished);
+        }
+
+        match self.cancel_flags.lock().unwrap().get(&job_id) {
+            Some(flag) => flag.store(true, Ordering::SeqCst),
+            None => job.mark_finished(&mut conn, Some(&Error::ScrapeCancelled))?
+        }
+
+        Ok(job)
+    }
+
+    /// Records a new job for the given user and runs it on the current thread,
+    /// returning what `run` returned once the job is finished.
+    ///
+    /// This is for jobs that need something only the caller has, like an
+    /// upload, so they can't be queued. They still take up the user's job slot
+    /// while they run, and can be cancelled like any other job.
+    pub fn run_now<T>(
+        &self,
+        user: &User,
+        mode: ScrapeMode,
+        run: impl FnOnce(&ScrapeJob, &AtomicBool) -> Result<T, Error>
+    ) -> Result<T, Error> {
+        let mut job = {
+            let mut conn = self.db.get()?;
+            ScrapeJob::create_new(&mut conn, user.user_id, mode, None, None)?
+        };
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.cancel_flags.lock().unwrap().insert(job.job_id, cancelled.clone());
+
+        execute(&self.db, &self.cancel_flags, &mut job, |job| run(job, &*cancelled))
+    }
+
+    /// Runs the given job on a new thread, recording its outcome when it's done.
+    fn spawn(&self, mut job: ScrapeJob) {
+        let db = self.db.clone();
+        let store = self.store.clone();
+        let cancel_flags = self.cancel_flags.clone();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        cancel_flags.lock().unwrap().insert(job.job_id, cancelled.clone());
+
+        thread::spawn(move || {
+            let _ = execute(&db, &cancel_flags, &mut job, |job| match job.mode {
+                ScrapeMode::Verify => verify_archived_tracks(&db, job, &cancelled),
+                ScrapeMode::ArchiveAudio => archive_liked_audio(&db, &store, job, &cancelled),
+                ScrapeMode::Export => export_archive(&db, &store, job, &cancelled),
+                // Imports can't be picked back up, since the upload is gone
+                ScrapeMode::Import => Err(Error::ScrapeInterrupted),
+                _ => run_job(&db, &store, job, &cancelled)
+            });
+        });
+    }
+}
+
+/// Marks the given job as running, runs it with `run` and records its outcome,
+/// removing its cancellation flag from `cancel_flags` once it's done.
+fn execute<T>(
+    db: &DbPool,
+    cancel_flags: &Mutex<HashMap<i32, Arc<AtomicBool>>>,
+    job: &mut ScrapeJob,
+    run: impl FnOnce(&ScrapeJob) -> Result<T, Error>
+) -> Result<T, Error> {
+    let started = db.get()
+        .map_err(Error::from)
+        .and_then(|mut conn| job.mark_running(&mut conn));
+    if let Err(e) = started {
+        eprintln!("Failed to start scrape job {}: {:?}", job.job_id, e);
+        cancel_flags.lock().unwrap().remove(&job.job_id);
+        return Err(e);
+    }
+
+    // A panicking job still has to be marked as finished, or it would hold on
+    // to the user's job slot until the server restarts
+    let result = panic::catch_unwind(AssertUnwindSafe(|| run(job)))
+        .unwrap_or(Err(Error::ScrapePanicked));
+    match &result {
+        Err(Error::ScrapeCancelled) => {
+            let _ = SSE.push(job.user_id, "update", &SseEvent::Cancelled);
+        },
+        Err(e) => eprintln!("Scrape job {} failed: {:?}", job.job_id, e),
+        Ok(_) => {}
+    }
+
+    let finished = db.get()
+        .map_err(Error::from)
+        .and_then(|mut conn| job.mark_finished(&mut conn, result.as_ref().err()));
+    if let Err(e) = finished {
+        eprintln!("Failed to record outcome of scrape job {}: {:?}", job.job_id, e);
+    }
+    cancel_flags.lock().unwrap().remove(&job.job_id);
+
+    result
+}
+
+/// Returns `Error::ScrapeCancelled` if the given flag has been set.
+pub fn check_cancelled(cancelled: &AtomicBool) -> Result<(), Error> {
+    if cancelled.load(Ordering::SeqCst) {
+        Err(Error::ScrapeCancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// Fetches newest-first items in growing windows until the fetched items
+/// include one whose id is in `known_ids`, returning only the items newer than
+/// that one.
+///
+/// `fetch` is given the number of recent items to fetch, `ids` lists the ids of
+/// the fetched items in order and `truncate` shortens the fetched items to the
+/// given length. Fetching stops early once fewer items come back than were
+/// asked for or `limit` is reached.
+///
+/// Each window is fetched from the start again, so in the worst case this
+/// costs twice as many requests as fetching everything up to the first known
+/// item would have.
+fn fetch_incrementally<R>(
+    limit: u64,
+    known_ids: &HashSet<i64>,
+    cancelled: &AtomicBool,
+    mut fetch: impl FnMut(u64) -> Result<R, Error>,
+    ids: impl Fn(&R) -> Result<Vec<i64>, Error>,
+    truncate: impl Fn(&mut R, usize)
+) -> Result<R, Error> {
+    let mut window = INCREMENTAL_WINDOW.min(limit);
+
+    loop {
+        check_cancelled(cancelled)?;
+        let mut fetched = fetch(window)?;
+        let fetched_ids = ids(&fetched)?;
+
+        if let Some(pos) = fetched_ids.iter().position(|id| known_ids.contains(id)) {
+            truncate(&mut fetched, pos);
+            return Ok(fetched);
+        }
+
+        if (fetched_ids.len() as u64) < window || window >= limit {
+            return Ok(fetched);
+        }
+
+        window = window.saturating_mul(2).min(limit);
+    }
+}
+
+/// Scrapes the data requested by the given job and stores it in the database.
+///
+/// `cancelled` is checked before and after each scraping phase. orange-zest
+/// gives us no way to abort a download from within its progress callback, so
+/// once cancellation is noticed there we simply stop forwarding progress and
+/// bail out as soon as the current phase returns. Nothing is written to the
+/// database until both phases are done, so a cancelled job leaves no trace
+/// behind; cancellation is no longer honored once writing has started.
+///
+/// Everything is written by `store_scraped`. Once it's written, the artwork and
+/// avatars of what was scraped are mirrored into the blob store.
+fn run_job(db: &DbPool, store: &dyn BlobStore, job: &ScrapeJob, cancelled: &AtomicBool) -> Result<(), Error> {
+    let (user, known_track_ids, known_playlist_ids) = {
+        let mut conn = db.get()?;
+        let user = User::load_id(&mut conn, job.user_id)?;
+        let known_track_ids: HashSet<i64> = user.liked_track_ids(&mut *conn)?.into_iter().collect();
+        let known_playlist_ids: HashSet<i64> = user.playlist_ids(&mut *conn)?.into_iter().collect();
+
+        (user, known_track_ids, known_playlist_ids)
+    };
+    let zester = zester_for(&user)?;
+    // Needed to tell the user's own playlists apart from the ones they liked
+    let own_sc_user_id = ScApi::for_user(&user)?.own_user_id()?;
+
+    let num_recent_likes = job.num_recent_likes.map(|n| n as u64).unwrap_or(std::u64::MAX);
+    let num_recent_playlists = job.num_recent_playlists.map(|n| n as u64).unwrap_or(std::u64::MAX);
+
+    let fetch_likes = |num: u64| -> Result<_, Error> {
+        Ok(zester.likes(num, |e| {
+            if cancelled.load(Ordering::SeqCst) {
+                return;
+            }
+
+            // We don't really care about errors here
+            let _ = SSE.push(
+                job.user_id,
+                "update",
+                &SseEvent::LikesScrapingEvent(e)
+            );
+        })?)
+    };
+    let fetch_playlists = |num: u64| -> Result<_, Error> {
+        Ok(zester.playlists(num, |e| {
+            if cancelled.load(Ordering::SeqCst) {
+                return;
+            }
+
+            // We don't really care about errors here
+            let _ = SSE.push(
+                job.user_id,
+                "update",
+                &SseEvent::PlaylistsScrapingEvent(e)
+            );
+        })?)
+    };
+
+    let (likes, playlists) = match job.mode {
+        ScrapeMode::Full => {
+            check_cancelled(cancelled)?;
+            let likes = fetch_likes(num_recent_likes)?;
+            check_cancelled(cancelled)?;
+            let playlists = fetch_playlists(num_recent_playlists)?;
+
+            (likes, playlists)
+        },
+        ScrapeMode::Incremental => {
+            let likes = fetch_incrementally(
+                num_recent_likes,
+                &known_track_ids,
+                cancelled,
+                fetch_likes,
+                |l| l.collections.iter().map(|c| required(c.track.id, "track.id")).collect(),
+                |l, len| l.collections.truncate(len)
+            )?;
+            let playlists = fetch_incrementally(
+                num_recent_playlists,
+                &known_playlist_ids,
+                cancelled,
+                fetch_playlists,
+                |p| p.playlists.iter().map(|p| required(p.id, "playlist.id")).collect(),
+                |p, len| p.playlists.truncate(len)
+            )?;
+
+            (likes, playlists)
+        },
+        ScrapeMode::Verify | ScrapeMode::ArchiveAudio | ScrapeMode::Export | ScrapeMode::Import => unreachable!("{:?} jobs don't scrape", job.mode)
+    };
+    check_cancelled(cancelled)?;
+
+    let asset_urls = store_scraped(db, job, &user, own_sc_user_id, &likes, &playlists)?;
+
+    // What was scraped is stored either way, so this doesn't fail the job
+    if let Err(e) = mirror_assets(db, store, &asset_urls) {
+        eprintln!("Failed to mirror assets for scrape job {}: {:?}", job.job_id, e);
+    }
+
+    let _ = SSE.push(
+        job.user_id,
+        "update",
+        &SseEvent::Complete
+    );
+
+    Ok(())
+}
+
+/// Stores the given scraped likes and playlists for the given job's user,
+/// returning the URLs of their artwork and avatars.
+///
+/// Everything is written in a single transaction, so if any write fails, or
+/// anything that was scraped lacks a field we need, the archive is left
+/// exactly as it was before the job.
+pub fn store_scraped(
+    db: &DbPool,
+    job: &ScrapeJob,
+    user: &User,
+    own_sc_user_id: i64,
+    likes: &Likes,
+    playlists: &Playlists
+) -> Result<Vec<String>, Error> {
+    // The artwork and avatars of everything that was scraped
+    let mut asset_urls = vec![];
+
+    let mut conn = db.get()?;
+    let mut transaction = conn.transaction()?;
+    let mut scraped_likes = vec![];
+    let mut scraped_playlists = vec![];
+
+    for like in likes.collections.iter() {
+        let track = &like.track;
+        let sc_user = SoundCloudUser::try_from(required(track.user.as_ref(), "track.user")?)?;
+        let stored = Track::try_from(track)?;
+        stored.create_new(&mut transaction, &sc_user)?;
+        asset_urls.extend(track.artwork_url.clone());
+        asset_urls.extend(sc_user.avatar_url);
+
+        // SoundCloud doesn't always say when something was liked
+        let liked_at = like.created_at.as_deref().map(|t| parse_sc_timestamp(Some(t))).transpose()?;
+        scraped_likes.push((stored.track_id, liked_at));
+    }
+
+    // A full scrape of every like tells us exactly what is liked right
+    // now, so anything else we thought was liked has been unliked since.
+    // Any other scrape only tells us about some of the likes.
+    //
+    // The likes stored right now are used rather than the ones that were
+    // stored when the job started, so that we don't overwrite anything
+    // that changed while we were scraping
+    if job.mode == ScrapeMode::Full && job.num_recent_likes.is_none() {
+        let scraped: HashSet<i64> = scraped_likes.iter().map(|(id, _)| *id).collect();
+        let removed: Vec<i64> = user.liked_track_ids(&mut transaction)?
+            .into_iter()
+            .filter(|id| !scraped.contains(id))
+            .collect();
+
+        user.record_removed_likes(&mut transaction, &removed)?;
+    }
+
+    user.record_likes(&mut transaction, &scraped_likes)?;
+
+    for sc_playlist in playlists.playlists.iter() {
+        for track in required(sc_playlist.tracks.as_ref(), "playlist.tracks")? {
+            let sc_user = SoundCloudUser::try_from(required(track.user.as_ref(), "track.user")?)?;
+            Track::try_from(track)?.create_new(&mut transaction, &sc_user)?;
+            asset_urls.extend(track.artwork_url.clone());
+            asset_urls.extend(sc_user.avatar_url);
+        }
+
+        let playlist = Playlist::try_from(sc_playlist)?;
+        let sc_user = SoundCloudUser::try_from(required(sc_playlist.user.as_ref(), "playlist.user")?)?;
+        playlist.create_new(&mut transaction, &sc_user)?;
+        asset_urls.extend(sc_user.avatar_url);
+
+        let relation = if playlist.sc_user_id == own_sc_user_id {
+            PlaylistRelation::Owned
+        } else {
+            PlaylistRelation::Liked
+        };
+        scraped_playlists.push((playlist.playlist_id, relation));
+    }
+
+    user.record_playlists(&mut transaction, &scraped_playlists)?;
+    transaction.commit()?;
+
+    Ok(asset_urls)
+}
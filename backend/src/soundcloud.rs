@@ -0,0 +1,185 @@
+use super::*;
+
+use serde_derive::Deserialize;
+use chrono::{DateTime, Utc};
+
+use std::io::Read;
+
+/// The base URL of the SoundCloud API that the website itself uses
+const API_BASE: &str = "https://api-v2.soundcloud.com";
+
+/// The parts of `/me` that we care about
+#[derive(Deserialize)]
+struct Me {
+    id: i64
+}
+
+/// The parts of `/tracks/<id>` that tell us where to get a track's audio
+#[derive(Deserialize)]
+struct TrackMedia {
+    media: Option<Media>
+}
+
+#[derive(Deserialize)]
+struct Media {
+    transcodings: Vec<Transcoding>
+}
+
+/// One of the formats a track's audio is available in
+#[derive(Deserialize)]
+struct Transcoding {
+    /// Responds with a `StreamLocation` when requested
+    url: String,
+    format: TranscodingFormat
+}
+
+#[derive(Deserialize)]
+struct TranscodingFormat {
+    /// `progressive` for a single file, `hls` for a playlist of segments
+    protocol: String,
+    mime_type: String
+}
+
+/// Where a transcoding can be downloaded from
+#[derive(Deserialize)]
+struct StreamLocation {
+    url: String
+}
+
+/// A track's audio on its way in from SoundCloud
+pub struct AudioStream {
+    /// The MIME type of the audio
+    pub content_type: String,
+    pub reader: Box<dyn Read + Send>
+}
+
+/// A minimal client for SoundCloud's API that acts on behalf of one of our users.
+///
+/// orange-zest covers bulk scraping of likes and playlists; this covers the
+/// few other requests it has no API for.
+pub struct ScApi {
+    oauth_token: String,
+    client_id: String
+}
+
+impl ScApi {
+    /// Creates a client with the SoundCloud credentials stored for the given
+    /// user.
+    pub fn for_user(user: &User) -> Result<Self, Error> {
+        match (user.sc_oauth_token.clone(), user.sc_client_id.clone()) {
+            (Some(oauth_token), Some(client_id)) => Ok(Self { oauth_token, client_id }),
+            _ => Err(Error::ScAuthTokensNotPresent)
+        }
+    }
+
+    /// Makes an authenticated GET request to the given API URL.
+    ///
+    /// Errors if the request could not be made at all; HTTP error statuses are
+    /// left to the caller.
+    fn get(&self, url: &str) -> Result<ureq::Response, Error> {
+        let response = ureq::get(url)
+            .set("Authorization", &format!("OAuth {}", self.oauth_token))
+            .query("client_id", &self.client_id)
+            .call();
+
+        if let Some(e) = response.synthetic_error() {
+            return Err(Error::RequestError(e.to_string()));
+        }
+
+        Ok(response)
+    }
+
+    /// Looks up the id of the SoundCloud user these credentials belong to.
+    pub fn own_user_id(&self) -> Result<i64, Error> {
+        let response = self.get(&format!("{}/me", API_BASE))?;
+
+        if !response.ok() {
+            return Err(Error::HttpError(response.status()));
+        }
+
+        let me: Me = serde_json::from_reader(response.into_reader())?;
+        Ok(me.id)
+    }
+
+    /// Checks whether the track with the given id can still be found on
+    /// SoundCloud.
+    ///
+    /// Errors with `Error::ScAuthRejected` if SoundCloud doesn't accept our
+    /// credentials, since that says nothing about the track.
+    pub fn track_availability(&self, track_id: i64) -> Result<TrackAvailability, Error> {
+        let response = self.get(&format!("{}/tracks/{}", API_BASE, track_id))?;
+
+        match response.status() {
+            200..=299 => Ok(TrackAvailability::Available),
+            404 | 410 => Ok(TrackAvailability::Deleted),
+            403 => Ok(TrackAvailability::Private),
+            401 => Err(Error::ScAuthRejected),
+            status => Err(Error::HttpError(status))
+        }
+    }
+
+    /// Starts downloading the audio of the track with the given id.
+    ///
+    /// Responds with `None` if the track can't be found or has no audio that
+    /// can be downloaded as a single file. Errors with `Error::ScAuthRejected`
+    /// if SoundCloud doesn't accept our credentials.
+    pub fn track_audio(&self, track_id: i64) -> Result<Option<AudioStream>, Error> {
+        let response = self.get(&format!("{}/tracks/{}", API_BASE, track_id))?;
+
+        match response.status() {
+            200..=299 => {},
+            403 | 404 | 410 => return Ok(None),
+            401 => return Err(Error::ScAuthRejected),
+            status => return Err(Error::HttpError(status))
+        }
+
+        let track: TrackMedia = serde_json::from_reader(response.into_reader())?;
+        let transcoding = match track.media
+            .and_then(|m| m.transcodings.into_iter().find(|t| t.format.protocol == "progressive"))
+        {
+            Some(transcoding) => transcoding,
+            None => return Ok(None)
+        };
+
+        let response = self.get(&transcoding.url)?;
+        if !response.ok() {
+            return Err(Error::HttpError(response.status()));
+        }
+        let location: StreamLocation = serde_json::from_reader(response.into_reader())?;
+
+        // The stream itself is served from a CDN that doesn't need our
+        // credentials
+        let response = ureq::get(&location.url).call();
+        if let Some(e) = response.synthetic_error() {
+            return Err(Error::RequestError(e.to_string()));
+        }
+        if !response.ok() {
+            return Err(Error::HttpError(response.status()));
+        }
+
+        Ok(Some(AudioStream {
+            content_type: transcoding.format.mime_type,
+            reader: Box::new(response.into_reader())
+        }))
+    }
+}
+
+/// Unwraps a field of something SoundCloud sent that we can't do without.
+///
+/// Errors with `Error::MissingField`, naming the given field, if it's missing.
+pub fn required<T>(value: Option<T>, field: &str) -> Result<T, Error> {
+    value.ok_or_else(|| Error::MissingField(field.into()))
+}
+
+/// Parses a timestamp in either of the formats SoundCloud's APIs use.
+///
+/// Errors with `Error::MalformedTimestamp` if the timestamp is missing or in
+/// neither format.
+pub fn parse_sc_timestamp(timestamp: Option<&str>) -> Result<DateTime<Utc>, Error> {
+    let timestamp = timestamp.ok_or_else(|| Error::MalformedTimestamp("".into()))?;
+
+    DateTime::parse_from_rfc3339(timestamp)
+        .or_else(|_| DateTime::parse_from_str(timestamp, "%Y/%m/%d %H:%M:%S %z"))
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| Error::MalformedTimestamp(timestamp.into()))
+}
@@ -2,12 +2,15 @@ use json_structs::{AuthCredentials, RegisterInfo, UserInfo};
 use rocket::local::Client as HttpClient;
 use postgres::{Client, NoTls};
 use crate::{rocket, Error};
-use crate::create_tables;
-use rocket::http::{Status, StatusClass, ContentType};
+use crate::run_migrations;
+use rocket::http::{Status, StatusClass, ContentType, Header};
 use std::process::Command;
 use dotenv::dotenv;
 use serde_json::Value;
+use chrono::{DateTime, Utc};
 use sse_client::EventSource;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use crate::*;
 
 impl Default for Track {
@@ -16,7 +19,7 @@ impl Default for Track {
             track_id: 3234,
             sc_user_id: 102832,
             length_ms: 4039482,
-            created_at: "2019-09-10T16:07:05Z".into(),
+            created_at: "2019-09-10T16:07:05Z".parse().unwrap(),
             title: "Database Testing Track".into(),
             description: "This is a track for testing the database".into(),
             likes_count: 4838,
@@ -40,16 +43,105 @@ impl Default for SoundCloudUser {
     }
 }
 
-fn test_client() -> Result<Client, Error> {
+impl Default for Playlist {
+    fn default() -> Self {
+        Playlist {
+            playlist_id: 82334,
+            sc_user_id: 102832,
+            track_ids: vec![3234],
+            num_tracks: 1,
+            length_ms: 4039482,
+            created_at: "2019-09-17T06:29:59Z".parse().unwrap(),
+            title: "My Killer Tunes".into(),
+            permalink_url: "https://sadfacefakelink.cupcake".into(),
+            description: "This playlist slays dude. Play it in the car".into(),
+            likes_count: 9238,
+            is_album: false
+        }
+    }
+}
+
+// Config for a fresh temporary database
+fn test_db_config() -> postgres::Config {
     dotenv().ok();
 
     let output = Command::new("pg_tmp").arg("-t").output().unwrap();
-    let mut client = Client::connect(&String::from_utf8(output.stdout).unwrap(), NoTls)?;
-    create_tables(&mut client)?;
+    String::from_utf8(output.stdout).unwrap().trim().parse().unwrap()
+}
+
+fn test_client() -> Result<Client, Error> {
+    let mut client = test_db_config().connect(NoTls)?;
+    run_migrations(&mut client)?;
 
     Ok(client)
 }
 
+fn test_pool() -> Result<DbPool, Error> {
+    db_pool(test_db_config())
+}
+
+// A fresh temporary directory for a blob store
+fn test_store_dir() -> PathBuf {
+    static NEXT_STORE: AtomicUsize = AtomicUsize::new(0);
+
+    std::env::temp_dir().join(format!(
+        "crate-test-blobs-{}-{}",
+        std::process::id(),
+        NEXT_STORE.fetch_add(1, Ordering::SeqCst)
+    ))
+}
+
+// A blob store in a fresh temporary directory
+fn test_store() -> Result<SharedBlobStore, Error> {
+    Ok(Arc::new(FsBlobStore::new(test_store_dir())?))
+}
+
+// Puts, reads, lists and deletes a blob in the given store
+fn check_blob_store(store: &dyn BlobStore) -> Result<(), Error> {
+    let contents: Vec<u8> = (0..200).collect();
+
+    let first = store.put(&mut &contents[..])?;
+    let second = store.put(&mut &contents[..])?;
+    assert_eq!(first.hash, second.hash);
+    assert_eq!(first.path, blob_path(&first.hash));
+    assert_eq!(first.size_bytes, 200);
+
+    let mut blob = store.open(&first.hash)?;
+    let mut read = vec![];
+    blob.read_to_end(&mut read)?;
+    assert_eq!(read, contents);
+
+    assert_eq!(blob.seek(SeekFrom::End(-50))?, 150);
+    let mut read = vec![];
+    blob.read_to_end(&mut read)?;
+    assert_eq!(read, &contents[150..]);
+
+    assert!(store.list()?.iter().any(|b| b.hash == first.hash));
+
+    store.delete(&first.hash)?;
+    // Deleting something that's already gone is fine
+    store.delete(&first.hash)?;
+    assert!(!store.list()?.iter().any(|b| b.hash == first.hash));
+    if let Err(Error::IoErr(e)) = store.open(&first.hash) {
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    } else {
+        panic!("deleted blob could still be opened");
+    }
+
+    Ok(())
+}
+
+// Pairs the given track ids with like times a second apart, the first one
+// being the most recent
+fn liked_just_now(track_ids: &[i64]) -> Vec<(i64, Option<DateTime<Utc>>)> {
+    let now = Utc::now();
+    track_ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (*id, Some(now - chrono::Duration::seconds(i as i64))))
+        .collect()
+}
+
 // Quickly set up a test user with SC auth if credentials are available
 //
 // Returns the RegisterInfo used to set the user up with.
@@ -83,18 +175,22 @@ fn setup_test_user(client: &HttpClient) -> Result<RegisterInfo, Error> {
 
 #[test]
 fn clear_liked_tracks() -> Result<(), Error> {
-    let client = HttpClient::new(rocket(test_client()?)?).unwrap();
-    let db = client.rocket().state::<DbClient>().unwrap();
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
     let rinfo = setup_test_user(&client)?;
 
     {
-        let mut conn = db.lock().unwrap();
-        let mut user = User::load_username(&mut conn, &rinfo.username)?;
-    
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+        let sc_user = SoundCloudUser::default();
+
         let ids = vec![1, 2, 3];
-        user.update_liked_track_ids(&mut conn, ids.clone())?;
-        user = User::load_username(&mut conn, &rinfo.username)?;
-        assert_eq!(user.liked_track_ids, ids);
+        for id in ids.iter() {
+            Track { track_id: *id, ..Track::default() }.create_new(&mut *conn, &sc_user)?;
+        }
+
+        user.record_likes(&mut *conn, &liked_just_now(&ids))?;
+        assert_eq!(user.liked_track_ids(&mut *conn)?, ids);
     }
 
     let response = client
@@ -103,11 +199,11 @@ fn clear_liked_tracks() -> Result<(), Error> {
     assert_eq!(response.status().class(), StatusClass::Success);
 
     {
-        let mut conn = db.lock().unwrap();
+        let mut conn = db.get()?;
         let user = User::load_username(&mut conn, &rinfo.username)?;
 
         let empty_vec: Vec<i64> = vec![];
-        assert_eq!(user.liked_track_ids, empty_vec);
+        assert_eq!(user.liked_track_ids(&mut *conn)?, empty_vec);
     }
 
     
@@ -116,18 +212,26 @@ fn clear_liked_tracks() -> Result<(), Error> {
 
 #[test]
 fn clear_playlists() -> Result<(), Error> {
-    let client = HttpClient::new(rocket(test_client()?)?).unwrap();
-    let db = client.rocket().state::<DbClient>().unwrap();
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
     let rinfo = setup_test_user(&client)?;
 
     {
-        let mut conn = db.lock().unwrap();
-        let mut user = User::load_username(&mut conn, &rinfo.username)?;
-    
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+        let sc_user = SoundCloudUser::default();
+
         let ids = vec![1, 2, 3];
-        user.update_playlist_ids(&mut conn, ids.clone())?;
-        user = User::load_username(&mut conn, &rinfo.username)?;
-        assert_eq!(user.playlist_ids, ids);
+        for id in ids.iter() {
+            Playlist { playlist_id: *id, ..Playlist::default() }.create_new(&mut *conn, &sc_user)?;
+        }
+
+        let playlists: Vec<_> = ids.iter().map(|id| (*id, PlaylistRelation::Owned)).collect();
+        user.record_playlists(&mut *conn, &playlists)?;
+
+        let mut playlist_ids = user.playlist_ids(&mut *conn)?;
+        playlist_ids.sort();
+        assert_eq!(playlist_ids, ids);
     }
 
     let response = client
@@ -136,11 +240,11 @@ fn clear_playlists() -> Result<(), Error> {
     assert_eq!(response.status().class(), StatusClass::Success);
 
     {
-        let mut conn = db.lock().unwrap();
+        let mut conn = db.get()?;
         let user = User::load_username(&mut conn, &rinfo.username)?;
 
         let empty_vec: Vec<i64> = vec![];
-        assert_eq!(user.playlist_ids, empty_vec);
+        assert_eq!(user.playlist_ids(&mut *conn)?, empty_vec);
     }
 
     
@@ -149,8 +253,8 @@ fn clear_playlists() -> Result<(), Error> {
 
 #[test]
 fn most_liked_artist() -> Result<(), Error> {
-    let client = HttpClient::new(rocket(test_client()?)?).unwrap();
-    let db = client.rocket().state::<DbClient>().unwrap();
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
     let rinfo = setup_test_user(&client)?;
 
     let mut tracks: Vec<_> = std::iter::repeat(Track::default()).take(5).collect();
@@ -172,19 +276,21 @@ fn most_liked_artist() -> Result<(), Error> {
     users[0].sc_user_id = 1;
     users[1].sc_user_id = 2;
     {
-        let mut conn = db.lock().unwrap();
+        let mut conn = db.get()?;
         let user = User::load_username(&mut conn, &rinfo.username)?;
-        user.update_liked_track_ids(&mut conn, tracks.iter().map(|t| t.track_id))?;
 
         for user in users.clone() {
-            user.create_new(&mut conn)?;
+            user.create_new(&mut *conn)?;
         }
-        for track in tracks {
+        for track in tracks.iter() {
             track.create_new(
-                &mut conn,
+                &mut *conn,
                 users.iter().find(|u| u.sc_user_id == track.sc_user_id).unwrap()
             )?;
         }
+
+        let ids: Vec<i64> = tracks.iter().map(|t| t.track_id).collect();
+        user.record_likes(&mut *conn, &liked_just_now(&ids))?;
     }
 
     let mut response = client
@@ -200,8 +306,8 @@ fn most_liked_artist() -> Result<(), Error> {
 
 #[test]
 fn average_playback_count() -> Result<(), Error> {
-    let client = HttpClient::new(rocket(test_client()?)?).unwrap();
-    let db = client.rocket().state::<DbClient>().unwrap();
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
     let rinfo = setup_test_user(&client)?;
 
     let mut tracks: Vec<_> = std::iter::repeat(Track::default()).take(5).collect();
@@ -214,14 +320,16 @@ fn average_playback_count() -> Result<(), Error> {
     tracks[4].track_id = 5;
 
     {
-        let mut conn = db.lock().unwrap();
+        let mut conn = db.get()?;
         let user = User::load_username(&mut conn, &rinfo.username)?;
-        user.update_liked_track_ids(&mut conn, tracks.iter().map(|t| t.track_id))?;
-        sc_user.create_new(&mut conn)?;
+        sc_user.create_new(&mut *conn)?;
 
         for track in tracks.clone() {
-            track.create_new(&mut conn, &sc_user)?;
+            track.create_new(&mut *conn, &sc_user)?;
         }
+
+        let ids: Vec<i64> = tracks.iter().map(|t| t.track_id).collect();
+        user.record_likes(&mut *conn, &liked_just_now(&ids))?;
     }
 
     let mut response = client
@@ -236,45 +344,1627 @@ fn average_playback_count() -> Result<(), Error> {
 }
 
 #[test]
-fn error_json() -> Result<(), Error> {
-    let client = HttpClient::new(rocket(test_client()?)?).unwrap();
-    setup_test_user(&client)?;
+fn scrape_jobs() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
 
-    let auth_creds = AuthCredentials {
-        oauth_token: "bla".into(),
-        client_id: "bla2".into()
+    let job_id = {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+
+        let mut job = ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Full, Some(5), None)?;
+        job.mark_running(&mut conn)?;
+        job.mark_finished(&mut conn, Some(&Error::ScAuthTokensNotPresent))?;
+        job.job_id
     };
 
-    // Set sc credentials to something invalid
+    let mut response = client
+        .get("/api/scrape-jobs")
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let jobs: Vec<ScrapeJobInfo> = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].job_id, job_id);
+    assert_eq!(jobs[0].status, ScrapeJobStatus::Failed);
+    assert_eq!(jobs[0].num_recent_likes, Some(5));
+    assert_eq!(jobs[0].num_recent_playlists, None);
+    assert!(jobs[0].started_at.is_some());
+    assert!(jobs[0].finished_at.is_some());
+    assert!(jobs[0].error.is_some());
+
+    let mut response = client
+        .get(format!("/api/scrape-jobs/{}", job_id))
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let job: ScrapeJobInfo = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(job.job_id, job_id);
+
     let response = client
-        .post("/api/set-auth-creds")
-        .header(ContentType::JSON)
-        .body(serde_json::to_string(&auth_creds).unwrap())
+        .get(format!("/api/scrape-jobs/{}", job_id + 1))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    Ok(())
+}
+
+#[test]
+fn scraped_track_missing_fields() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+
+    // SoundCloud sent a track without its uploader
+    let likes: orange_zest::api::likes::Likes = serde_json::from_value(serde_json::json!({
+        "collections": [{
+            "created_at": "2020/05/01 12:00:00 +0000",
+            "track": {
+                "id": 5678,
+                "duration": 1000,
+                "created_at": "2020/04/01 12:00:00 +0000",
+                "permalink_url": "https://broken.track"
+            }
+        }]
+    }))?;
+    let playlists: orange_zest::api::playlists::Playlists = serde_json::from_value(serde_json::json!({
+        "playlists": []
+    }))?;
+
+    let mut conn = db.get()?;
+    let user = User::load_username(&mut conn, &rinfo.username)?;
+    let mut job = ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Full, None, None)?;
+    job.mark_running(&mut conn)?;
+
+    let result = store_scraped(db, &job, &user, 1, &likes, &playlists);
+    match &result {
+        Err(Error::MissingField(f)) => assert_eq!(f, "track.user"),
+        r => panic!("expected a missing field error, got {:?}", r)
+    }
+    assert!(user.liked_track_ids(&mut *conn)?.is_empty());
+
+    // The job fails instead of being left running, so the user can scrape again
+    job.mark_finished(&mut conn, result.err().as_ref())?;
+    assert_eq!(job.status, ScrapeJobStatus::Failed);
+    ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Full, None, None)?;
+
+    Ok(())
+}
+
+#[test]
+fn scraped_stats_snapshots() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+
+    // The same track is liked and also in a playlist
+    let track = serde_json::json!({
+        "id": 5678,
+        "user_id": 1,
+        "duration": 1000,
+        "created_at": "2020/04/01 12:00:00 +0000",
+        "title": "Liked Track",
+        "permalink_url": "https://liked.track",
+        "user": {
+            "id": 1,
+            "username": "someone",
+            "permalink_url": "https://sc.com/someone"
+        }
+    });
+    let likes: orange_zest::api::likes::Likes = serde_json::from_value(serde_json::json!({
+        "collections": [{
+            "created_at": "2020/05/01 12:00:00 +0000",
+            "track": track
+        }]
+    }))?;
+    let playlists: orange_zest::api::playlists::Playlists = serde_json::from_value(serde_json::json!({
+        "playlists": [{
+            "id": 1234,
+            "user_id": 1,
+            "created_at": "2020/04/01 12:00:00 +0000",
+            "permalink_url": "https://sc.com/someone/sets/1234",
+            "tracks": [track],
+            "user": {
+                "id": 1,
+                "username": "someone",
+                "permalink_url": "https://sc.com/someone"
+            }
+        }]
+    }))?;
+
+    let mut conn = db.get()?;
+    let user = User::load_username(&mut conn, &rinfo.username)?;
+    let job = ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Full, None, None)?;
+    store_scraped(db, &job, &user, 1, &likes, &playlists)?;
+
+    let snapshots: i64 = conn.query_one(
+        "SELECT COUNT(*) FROM track_stats_snapshots WHERE track_id = 5678",
+        &[]
+    )?.get(0);
+    assert_eq!(snapshots, 1);
+
+    Ok(())
+}
+
+#[test]
+fn scraped_like_without_timestamp() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+
+    // SoundCloud didn't say when the track was liked
+    let likes: orange_zest::api::likes::Likes = serde_json::from_value(serde_json::json!({
+        "collections": [{
+            "track": {
+                "id": 5678,
+                "user_id": 1,
+                "duration": 1000,
+                "created_at": "2020/04/01 12:00:00 +0000",
+                "permalink_url": "https://liked.track",
+                "user": {
+                    "id": 1,
+                    "username": "someone",
+                    "permalink_url": "https://sc.com/someone"
+                }
+            }
+        }]
+    }))?;
+    let playlists: orange_zest::api::playlists::Playlists = serde_json::from_value(serde_json::json!({
+        "playlists": []
+    }))?;
+
+    let mut conn = db.get()?;
+    let user = User::load_username(&mut conn, &rinfo.username)?;
+    let job = ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Full, None, None)?;
+    store_scraped(db, &job, &user, 1, &likes, &playlists)?;
+
+    assert_eq!(user.liked_track_ids(&mut *conn)?, vec![5678]);
+    let liked_at: Option<DateTime<Utc>> = conn.query_one(
+        "SELECT liked_at FROM user_likes WHERE user_id = $1 AND track_id = 5678",
+        &[&user.user_id]
+    )?.get(0);
+    assert_eq!(liked_at, None);
+
+    Ok(())
+}
+
+#[test]
+fn resume_unfinished_scrape_jobs() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let worker = client.rocket().state::<ScrapeWorker>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+
+    let job_id = {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+
+        let mut job = ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Full, None, None)?;
+        job.mark_running(&mut conn)?;
+        job.job_id
+    };
+
+    // A job that was running when the server stopped can't be picked back up
+    worker.resume_unfinished()?;
+
+    {
+        let mut conn = db.get()?;
+        let job = ScrapeJob::load_id(&mut conn, job_id)?;
+
+        assert_eq!(job.status, ScrapeJobStatus::Failed);
+        assert_eq!(job.error.unwrap(), "ScrapeInterrupted");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cancel_scrape_job() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+
+    // A queued job that nothing is running yet
+    let job_id = {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+        ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Full, None, None)?.job_id
+    };
+
+    let mut response = client
+        .post(format!("/api/scrape-jobs/{}/cancel", job_id))
         .dispatch();
     assert_eq!(response.status().class(), StatusClass::Success);
 
-    // Try to do scraping, observe 500 status code and JSON error payload
+    let job: ScrapeJobInfo = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(job.status, ScrapeJobStatus::Cancelled);
+    assert_eq!(job.error, None);
+
+    // Cancelling it a second time makes no sense
     let mut response = client
-        .get("/api/do-scraping?num_recent_likes=1&num_recent_playlists=1")
+        .post(format!("/api/scrape-jobs/{}/cancel", job_id))
         .dispatch();
+    assert_eq!(response.status(), Status::Conflict);
+    let err: Value = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(err.as_str().unwrap(), "ScrapeJobAlreadyFinished");
 
-    assert_eq!(response.status().class(), StatusClass::ServerError);
+    // A job that finishes while it's being cancelled keeps its outcome
+    {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+        let mut job = ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Full, None, None)?;
+        let mut stale = job.clone();
+
+        job.mark_finished(&mut conn, None)?;
+        match stale.mark_finished(&mut conn, Some(&Error::ScrapeCancelled)) {
+            Err(Error::ScrapeJobAlreadyFinished) => {},
+            r => panic!("expected the job to be finished already, got {:?}", r)
+        }
+        assert_eq!(ScrapeJob::load_id(&mut conn, job.job_id)?.status, ScrapeJobStatus::Succeeded);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn one_scrape_job_per_user() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+
+    let mut conn = db.get()?;
+    let user = User::load_username(&mut conn, &rinfo.username)?;
+
+    let mut job = ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Full, None, None)?;
+    match ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Full, None, None) {
+        Err(Error::ScrapeAlreadyRunning) => {},
+        r => panic!("expected ScrapeAlreadyRunning, got {:?}", r)
+    }
+
+    job.mark_running(&mut conn)?;
+    match ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Full, None, None) {
+        Err(Error::ScrapeAlreadyRunning) => {},
+        r => panic!("expected ScrapeAlreadyRunning, got {:?}", r)
+    }
+
+    // Once the first job is done another one can be started
+    job.mark_finished(&mut conn, None)?;
+    ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Full, None, None)?;
+
+    Ok(())
+}
+
+#[test]
+fn invalid_scrape_mode() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    setup_test_user(&client)?;
+
+    let mut response = client
+        .get("/api/do-scraping?mode=everything")
+        .dispatch();
+
+    assert_eq!(response.status(), Status::BadRequest);
     let err: Value = serde_json::from_str(&response.body_string().unwrap())?;
-    assert_eq!(err["OrangeZestErr"]["HttpError"].as_i64().unwrap(), 401);
+    assert_eq!(err["InvalidParameter"].as_str().unwrap(), "mode");
+
+    Ok(())
+}
+
+#[test]
+fn scrape_schedule() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+
+    let mut response = client
+        .get("/api/scrape-schedule")
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let info: ScrapeScheduleInfo = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(info, ScrapeScheduleInfo { schedule: ScrapeSchedule::Off, next_run_at: None });
+
+    let mut response = client
+        .post("/api/scrape-schedule")
+        .header(ContentType::JSON)
+        .body(serde_json::to_string(&ScrapeScheduleSettings { schedule: ScrapeSchedule::Daily }).unwrap())
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let info: ScrapeScheduleInfo = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(info.schedule, ScrapeSchedule::Daily);
+    assert!(info.next_run_at.is_some());
+
+    {
+        let mut conn = db.get()?;
+
+        // Turning the schedule on makes the first run due right away
+        let mut due = User::load_due_for_scheduled_scrape(&mut conn)?;
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].username, rinfo.username);
+
+        // Users without SoundCloud credentials would only get failed jobs
+        conn.execute(
+            "UPDATE users SET sc_oauth_token = NULL, sc_client_id = NULL WHERE username = $1",
+            &[&rinfo.username]
+        )?;
+        assert!(User::load_due_for_scheduled_scrape(&mut conn)?.is_empty());
+
+        due[0].schedule_next_scrape(&mut conn)?;
+        assert!(User::load_due_for_scheduled_scrape(&mut conn)?.is_empty());
+    }
+
+    let mut response = client
+        .post("/api/scrape-schedule")
+        .header(ContentType::JSON)
+        .body(serde_json::to_string(&ScrapeScheduleSettings { schedule: ScrapeSchedule::Off }).unwrap())
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let info: ScrapeScheduleInfo = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(info, ScrapeScheduleInfo { schedule: ScrapeSchedule::Off, next_run_at: None });
+
+    Ok(())
+}
+
+#[test]
+fn like_history() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+
+    let mut tracks: Vec<_> = std::iter::repeat(Track::default()).take(3).collect();
+    let sc_user = SoundCloudUser::default();
+
+    tracks[0].track_id = 1;
+    tracks[1].track_id = 2;
+    tracks[2].track_id = 3;
+
+    {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+
+        for track in tracks.clone() {
+            track.create_new(&mut *conn, &sc_user)?;
+        }
+
+        // Track 3 was liked at first and then unliked
+        user.record_likes(&mut *conn, &liked_just_now(&[1, 2, 3]))?;
+        user.record_removed_likes(&mut *conn, &[3])?;
+    }
+
+    let liked_ids = |filter: &str| -> Result<Vec<i64>, Error> {
+        let mut response = client
+            .get(format!("/api/liked-tracks?filter={}", filter))
+            .dispatch();
+        assert_eq!(response.status().class(), StatusClass::Success);
+
+        let page: Page<TrackInfoBrief> = serde_json::from_str(&response.body_string().unwrap())?;
+        let mut ids: Vec<i64> = page.items.iter().map(|t| t.track_id).collect();
+        ids.sort();
+        Ok(ids)
+    };
+
+    assert_eq!(liked_ids("current")?, vec![1, 2]);
+    assert_eq!(liked_ids("removed")?, vec![3]);
+    assert_eq!(liked_ids("all")?, vec![1, 2, 3]);
+
+    let mut response = client
+        .get("/api/like-history")
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let history: Vec<LikeHistoryEntry> = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(history.len(), 3);
+    assert_eq!(history[0].track.track_id, 3);
+    assert!(history[0].removed_at.is_some());
+    assert!(history[1..].iter().all(|e| e.removed_at.is_none()));
+
+    Ok(())
+}
+
+#[test]
+fn like_order() -> Result<(), Error> {
+    let mut db_client = test_client()?;
+    let rinfo = RegisterInfo {
+        username: "testusername".into(),
+        password: "testpass".into()
+    };
+    let user_id = User::create_new(&mut db_client, &rinfo, "key")?;
+    let user = User::load_id(&mut db_client, user_id)?;
+    let sc_user = SoundCloudUser::default();
+
+    for id in 1..=5 {
+        Track { track_id: id, ..Track::default() }.create_new(&mut db_client, &sc_user)?;
+    }
+
+    user.record_likes(&mut db_client, &liked_just_now(&[3, 2, 1]))?;
+    // A scrape of only the most recent likes
+    user.record_likes(&mut db_client, &liked_just_now(&[5, 4, 3]))?;
+    assert_eq!(user.liked_track_ids(&mut db_client)?, vec![5, 4, 3, 2, 1]);
+
+    user.record_removed_likes(&mut db_client, &[4])?;
+    user.record_likes(&mut db_client, &liked_just_now(&[4]))?;
+    assert_eq!(user.liked_track_ids(&mut db_client)?, vec![4, 5, 3, 2, 1]);
+
+    Ok(())
+}
+
+#[test]
+fn liked_tracks_newest_first() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+    let sc_user = SoundCloudUser::default();
+
+    {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+
+        for id in 1..=3 {
+            Track { track_id: id, ..Track::default() }.create_new(&mut *conn, &sc_user)?;
+        }
+
+        let liked_at = |s: &str| -> Option<DateTime<Utc>> { Some(s.parse().unwrap()) };
+        user.record_likes(&mut *conn, &[
+            (2, liked_at("2020-03-01T12:00:00Z")),
+            (1, liked_at("2020-01-01T12:00:00Z"))
+        ])?;
+        user.record_likes(&mut *conn, &[(3, liked_at("2020-02-01T12:00:00Z"))])?;
+    }
+
+    let mut response = client
+        .get("/api/liked-tracks")
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let page: Page<TrackInfoBrief> = serde_json::from_str(&response.body_string().unwrap())?;
+    let ids: Vec<i64> = page.items.iter().map(|t| t.track_id).collect();
+    assert_eq!(ids, vec![2, 3, 1]);
 
     Ok(())
 }
 
+#[test]
+fn liked_tracks_pages() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+    let sc_user = SoundCloudUser::default();
+    let other_sc_user = SoundCloudUser { sc_user_id: 2, ..SoundCloudUser::default() };
+
+    {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+
+        for id in 1..=5 {
+            let artist = if id == 5 { &other_sc_user } else { &sc_user };
+            Track {
+                track_id: id,
+                sc_user_id: artist.sc_user_id,
+                length_ms: id * 1000,
+                ..Track::default()
+            }.create_new(&mut *conn, artist)?;
+        }
+
+        user.record_likes(&mut *conn, &liked_just_now(&[1, 2, 3, 4, 5]))?;
+    }
+
+    let get_page = |query: &str| -> Result<Page<TrackInfoBrief>, Error> {
+        let mut response = client
+            .get(format!("/api/liked-tracks?{}", query))
+            .dispatch();
+        assert_eq!(response.status().class(), StatusClass::Success);
+
+        Ok(serde_json::from_str(&response.body_string().unwrap())?)
+    };
+    let ids = |page: &Page<TrackInfoBrief>| -> Vec<i64> {
+        page.items.iter().map(|t| t.track_id).collect()
+    };
+
+    let page = get_page("limit=2")?;
+    assert_eq!(ids(&page), vec![1, 2]);
+    assert_eq!(page.total, 5);
+
+    let page = get_page(&format!("limit=2&cursor={}", page.next_cursor.unwrap()))?;
+    assert_eq!(ids(&page), vec![3, 4]);
+
+    let page = get_page(&format!("limit=2&cursor={}", page.next_cursor.unwrap()))?;
+    assert_eq!(ids(&page), vec![5]);
+    assert_eq!(page.next_cursor, None);
+
+    let page = get_page("sort=length_ms&order=desc&min_length_ms=2000&max_length_ms=4000")?;
+    assert_eq!(ids(&page), vec![4, 3, 2]);
+    assert_eq!(page.total, 3);
+
+    let page = get_page(&format!("artist={}", other_sc_user.sc_user_id))?;
+    assert_eq!(ids(&page), vec![5]);
+
+    let page = get_page("created_after=2019-09-10T16:07:06Z")?;
+    assert_eq!(page.total, 0);
+
+    let response = client
+        .get("/api/liked-tracks?sort=popularity")
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    Ok(())
+}
+
+#[test]
+fn lost_tracks() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+
+    let mut tracks: Vec<_> = std::iter::repeat(Track::default()).take(4).collect();
+    let sc_user = SoundCloudUser::default();
+
+    for (i, track) in tracks.iter_mut().enumerate() {
+        track.track_id = i as i64 + 1;
+    }
+
+    {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+
+        for track in tracks.clone() {
+            track.create_new(&mut *conn, &sc_user)?;
+        }
+
+        // Track 3 used to be liked, and track 4 was never liked at all
+        user.record_likes(&mut *conn, &liked_just_now(&[1, 2, 3]))?;
+        user.record_removed_likes(&mut *conn, &[3])?;
+
+        Track::set_availability(&mut conn, 1, TrackAvailability::Available)?;
+        Track::set_availability(&mut conn, 2, TrackAvailability::Deleted)?;
+        Track::set_availability(&mut conn, 3, TrackAvailability::Private)?;
+        Track::set_availability(&mut conn, 4, TrackAvailability::Deleted)?;
+    }
+
+    let mut response = client
+        .get("/api/lost-tracks")
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let lost: Vec<TrackInfoBrief> = serde_json::from_str(&response.body_string().unwrap())?;
+    let mut lost: Vec<(i64, TrackAvailability)> = lost
+        .iter()
+        .map(|t| (t.track_id, t.availability))
+        .collect();
+    lost.sort_by_key(|(id, _)| *id);
+    assert_eq!(lost, vec![(2, TrackAvailability::Deleted), (3, TrackAvailability::Private)]);
+
+    let mut response = client
+        .get("/api/liked-tracks")
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let page: Page<TrackInfoBrief> = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(page.items.len(), 2);
+    assert!(page.items.iter().all(|t| t.last_verified_at.is_some()));
+
+    Ok(())
+}
+
+#[test]
+fn verify_with_rejected_credentials() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+
+    let auth_creds = AuthCredentials {
+        oauth_token: "bla".into(),
+        client_id: "bla2".into()
+    };
+
+    // Set sc credentials to something invalid
+    let response = client
+        .post("/api/set-auth-creds")
+        .header(ContentType::JSON)
+        .body(serde_json::to_string(&auth_creds).unwrap())
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let track = Track::default();
+    let job = {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+
+        track.create_new(&mut *conn, &SoundCloudUser::default())?;
+        user.record_likes(&mut *conn, &liked_just_now(&[track.track_id]))?;
+        Track::set_availability(&mut conn, track.track_id, TrackAvailability::Available)?;
+
+        ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Verify, None, None)?
+    };
+
+    // A 401 is about our credentials, not the track
+    match verify_archived_tracks(db, &job, &AtomicBool::new(false)) {
+        Err(Error::ScAuthRejected) => {},
+        r => panic!("expected ScAuthRejected, got {:?}", r)
+    }
+
+    let mut response = client
+        .get("/api/lost-tracks")
+        .dispatch();
+    let lost: Vec<TrackInfoBrief> = serde_json::from_str(&response.body_string().unwrap())?;
+    assert!(lost.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn requests_use_separate_connections() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    setup_test_user(&client)?;
+
+    // Holding on to a connection mustn't block requests that need the database
+    let _conn = db.get()?;
+
+    let response = client
+        .get("/api/me")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    Ok(())
+}
+
+#[test]
+fn error_json() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    setup_test_user(&client)?;
+
+    let auth_creds = AuthCredentials {
+        oauth_token: "bla".into(),
+        client_id: "bla2".into()
+    };
+
+    // Set sc credentials to something invalid
+    let response = client
+        .post("/api/set-auth-creds")
+        .header(ContentType::JSON)
+        .body(serde_json::to_string(&auth_creds).unwrap())
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    // Try to do scraping, observe 500 status code and JSON error payload
+    let mut response = client
+        .get("/api/do-scraping?num_recent_likes=1&num_recent_playlists=1")
+        .dispatch();
+
+    assert_eq!(response.status().class(), StatusClass::ServerError);
+    let err: Value = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(err["HttpError"].as_i64().unwrap(), 401);
+
+    Ok(())
+}
+
+#[test]
+fn rescrape_refreshes_metadata() -> Result<(), Error> {
+    let mut db_client = test_client()?;
+
+    let mut sc_user = SoundCloudUser::default();
+    let mut track = Track::default();
+    track.create_new(&mut db_client, &sc_user)?;
+
+    let refreshed_at = |db_client: &mut Client| -> Result<DateTime<Utc>, Error> {
+        Ok(db_client.query_one(
+            "SELECT last_refreshed_at FROM tracks WHERE track_id = $1",
+            &[&track.track_id]
+        )?.get(0))
+    };
+    let first_refresh = refreshed_at(&mut db_client)?;
+
+    sc_user.username = "superdude2".into();
+    track.title = "Renamed Track".into();
+    track.playback_count += 1000;
+    track.likes_count += 10;
+    let download_url = track.download_url.take();
+    track.create_new(&mut db_client, &sc_user)?;
+
+    assert_eq!(SoundCloudUser::load_id(&mut db_client, sc_user.sc_user_id)?, sc_user);
+
+    // A download URL that we knew about isn't forgotten
+    track.download_url = download_url;
+    assert_eq!(Track::load_id(&mut db_client, track.track_id)?, track);
+    assert!(refreshed_at(&mut db_client)? > first_refresh);
+
+    Ok(())
+}
+
+#[test]
+fn track_history() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    setup_test_user(&client)?;
+
+    let sc_user = SoundCloudUser::default();
+    let mut track = Track::default();
+
+    {
+        let mut conn = db.get()?;
+
+        for playback_count in &[100, 150, 400] {
+            track.playback_count = *playback_count;
+            track.create_new(&mut *conn, &sc_user)?;
+        }
+    }
+
+    let mut response = client
+        .get(format!("/api/track-info/{}/history", track.track_id))
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let history: Vec<TrackStatsSnapshot> = serde_json::from_str(&response.body_string().unwrap())?;
+    let playback_counts: Vec<i64> = history.iter().map(|s| s.playback_count).collect();
+    assert_eq!(playback_counts, vec![100, 150, 400]);
+    assert!(history.iter().all(|s| s.likes_count == track.likes_count));
+
+    Ok(())
+}
+
+#[test]
+fn search_archive() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+    let sc_user = SoundCloudUser::default();
+
+    {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+
+        let tracks = vec![
+            Track { track_id: 1, title: "Sunrise".into(), ..Track::default() },
+            Track { track_id: 2, description: "Made at sunrise".into(), ..Track::default() },
+            // Not in the user's archive
+            Track { track_id: 3, title: "Sunrise Remix".into(), ..Track::default() }
+        ];
+        for track in tracks.iter() {
+            track.create_new(&mut *conn, &sc_user)?;
+        }
+        user.record_likes(&mut *conn, &liked_just_now(&[1, 2]))?;
+
+        let playlist = Playlist {
+            track_ids: vec![],
+            num_tracks: 0,
+            description: "Songs for a sunrise".into(),
+            ..Playlist::default()
+        };
+        playlist.create_new(&mut *conn, &sc_user)?;
+        user.record_playlists(&mut *conn, &[(playlist.playlist_id, PlaylistRelation::Owned)])?;
+    }
+
+    let search = |q: &str| -> Result<Vec<SearchResult>, Error> {
+        let mut response = client
+            .get(format!("/api/search?q={}", q))
+            .dispatch();
+        assert_eq!(response.status().class(), StatusClass::Success);
+
+        Ok(serde_json::from_str(&response.body_string().unwrap())?)
+    };
+
+    let results = search("sunrise")?;
+    let found: Vec<(SearchResultKind, i64)> = results.iter().map(|r| (r.kind, r.id)).collect();
+    assert_eq!(found.len(), 3);
+    // Matches in the title rank higher than matches in the description
+    assert_eq!(found[0], (SearchResultKind::Track, 1));
+    assert!(found.contains(&(SearchResultKind::Track, 2)));
+    assert!(found.contains(&(SearchResultKind::Playlist, 82334)));
+    assert!(results.iter().all(|r| r.snippet.contains("<b>")));
+
+    let results = search("bayer")?;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].kind, SearchResultKind::Artist);
+    assert_eq!(results[0].id, sc_user.sc_user_id);
+
+    let response = client
+        .get("/api/search?q=")
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    Ok(())
+}
+
+#[test]
+fn artists() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+
+    let liked_artist = SoundCloudUser::default();
+    let other_artist = SoundCloudUser { sc_user_id: 2, username: "other".into(), ..SoundCloudUser::default() };
+    let playlist_maker = SoundCloudUser { sc_user_id: 3, username: "maker".into(), ..SoundCloudUser::default() };
+
+    {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+
+        for (id, artist) in &[(1, &liked_artist), (2, &liked_artist), (3, &other_artist)] {
+            Track { track_id: *id, sc_user_id: artist.sc_user_id, ..Track::default() }
+                .create_new(&mut *conn, artist)?;
+        }
+        user.record_likes(&mut *conn, &liked_just_now(&[1, 2, 3]))?;
+
+        let playlist = Playlist {
+            sc_user_id: playlist_maker.sc_user_id,
+            track_ids: vec![],
+            num_tracks: 0,
+            ..Playlist::default()
+        };
+        playlist.create_new(&mut *conn, &playlist_maker)?;
+        user.record_playlists(&mut *conn, &[(playlist.playlist_id, PlaylistRelation::Liked)])?;
+    }
+
+    let mut response = client
+        .get("/api/artists")
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let artists: Vec<ArtistSummary> = serde_json::from_str(&response.body_string().unwrap())?;
+    let counts: Vec<(i64, i64)> = artists.iter().map(|a| (a.info.sc_user_id, a.num_liked_tracks)).collect();
+    assert_eq!(counts, vec![(liked_artist.sc_user_id, 2), (other_artist.sc_user_id, 1), (playlist_maker.sc_user_id, 0)]);
+
+    let mut response = client
+        .get(format!("/api/artists/{}", liked_artist.sc_user_id))
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let artist: ArtistDetail = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(artist.info.username, liked_artist.username);
+    let mut liked_ids: Vec<i64> = artist.liked_tracks.iter().map(|t| t.track_id).collect();
+    liked_ids.sort();
+    assert_eq!(liked_ids, vec![1, 2]);
+    assert!(artist.playlists.is_empty());
+
+    let mut response = client
+        .get(format!("/api/artists/{}", playlist_maker.sc_user_id))
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let artist: ArtistDetail = serde_json::from_str(&response.body_string().unwrap())?;
+    assert!(artist.liked_tracks.is_empty());
+    assert_eq!(artist.playlists.len(), 1);
+
+    let response = client
+        .get("/api/artists/999")
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    Ok(())
+}
+
+#[test]
+fn playlist_tracks() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    setup_test_user(&client)?;
+
+    let sc_user = SoundCloudUser::default();
+    // Track 2 never made it into the database
+    let playlist = Playlist { track_ids: vec![3, 2, 1], num_tracks: 3, ..Playlist::default() };
+
+    {
+        let mut conn = db.get()?;
+
+        for id in &[1, 3] {
+            Track { track_id: *id, ..Track::default() }.create_new(&mut *conn, &sc_user)?;
+        }
+        playlist.create_new(&mut *conn, &sc_user)?;
+    }
+
+    let mut response = client
+        .get(format!("/api/playlist-info/{}/tracks", playlist.playlist_id))
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let tracks: Vec<PlaylistTrack> = serde_json::from_str(&response.body_string().unwrap())?;
+    let ids: Vec<i64> = tracks.iter().map(|t| t.track_id).collect();
+    assert_eq!(ids, vec![3, 2, 1]);
+    assert_eq!(tracks.iter().map(|t| t.position).collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!(tracks[0].track.as_ref().unwrap().track_id, 3);
+    assert!(tracks[1].track.is_none());
+    assert_eq!(tracks[2].track.as_ref().unwrap().username, sc_user.username);
+
+    let response = client
+        .get("/api/playlist-info/1/tracks")
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    Ok(())
+}
+
+#[test]
+fn track_info_batch() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    setup_test_user(&client)?;
+
+    let sc_user = SoundCloudUser::default();
+
+    {
+        let mut conn = db.get()?;
+
+        for id in &[1, 2] {
+            Track { track_id: *id, ..Track::default() }.create_new(&mut *conn, &sc_user)?;
+        }
+    }
+
+    let mut response = client
+        .post("/api/track-info/batch")
+        .header(ContentType::JSON)
+        .body("[1, 2, 99]")
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let lookups: HashMap<i64, TrackInfoLookup> = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(lookups.len(), 3);
+    for id in &[1, 2] {
+        match &lookups[id] {
+            TrackInfoLookup::Found(info) => assert_eq!(info.brief_info.track_id, *id),
+            TrackInfoLookup::NotFound => panic!("track {} should have been found", id)
+        }
+    }
+    if let TrackInfoLookup::Found(_) = lookups[&99] {
+        panic!("track 99 shouldn't have been found");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn byte_ranges() {
+    assert_eq!(parse_range("bytes=0-9", 100), ByteRange::Partial { start: 0, end: 9 });
+    assert_eq!(parse_range("bytes=90-", 100), ByteRange::Partial { start: 90, end: 99 });
+    assert_eq!(parse_range("bytes=90-200", 100), ByteRange::Partial { start: 90, end: 99 });
+    assert_eq!(parse_range("bytes=-10", 100), ByteRange::Partial { start: 90, end: 99 });
+    assert_eq!(parse_range("bytes=-200", 100), ByteRange::Partial { start: 0, end: 99 });
+    assert_eq!(parse_range("bytes=100-", 100), ByteRange::Unsatisfiable);
+    assert_eq!(parse_range("bytes=-0", 100), ByteRange::Unsatisfiable);
+
+    // Anything we don't support gets the whole file
+    assert_eq!(parse_range("bytes=0-9,20-29", 100), ByteRange::Full);
+    assert_eq!(parse_range("bytes=9-0", 100), ByteRange::Full);
+    assert_eq!(parse_range("lines=0-9", 100), ByteRange::Full);
+    assert_eq!(parse_range("bytes=abc", 100), ByteRange::Full);
+}
+
+#[test]
+fn blob_store_dedupes() -> Result<(), Error> {
+    let store = test_store()?;
+
+    let first = store.put(&mut &b"some audio"[..])?;
+    let second = store.put(&mut &b"some audio"[..])?;
+    let other = store.put(&mut &b"other audio"[..])?;
+
+    assert_eq!(first.hash, second.hash);
+    assert_eq!(first.path, second.path);
+    assert_eq!(first.size_bytes, 10);
+    assert_ne!(first.hash, other.hash);
+
+    let mut contents = vec![];
+    store.open(&first.hash)?.read_to_end(&mut contents)?;
+    assert_eq!(contents, b"some audio");
+
+    Ok(())
+}
+
+#[test]
+fn fs_blob_store() -> Result<(), Error> {
+    check_blob_store(&FsBlobStore::new(test_store_dir())?)
+}
+
+// Needs an S3-compatible service, such as MinIO, with a bucket to test in
+#[test]
+#[ignore]
+fn s3_blob_store() -> Result<(), Error> {
+    dotenv().ok();
+
+    check_blob_store(&S3BlobStore::new(
+        &env::var("S3_TEST_ENDPOINT").unwrap(),
+        &env::var("S3_TEST_BUCKET").unwrap(),
+        &env::var("S3_TEST_REGION").unwrap_or_else(|_| "us-east-1".into()),
+        &env::var("S3_TEST_ACCESS_KEY").unwrap(),
+        &env::var("S3_TEST_SECRET_KEY").unwrap()
+    )?)
+}
+
+#[test]
+fn verify_and_collect_blobs() -> Result<(), Error> {
+    let db = test_pool()?;
+    let dir = test_store_dir();
+    let store = FsBlobStore::new(&dir)?;
+
+    let referenced = store.put(&mut &b"referenced"[..])?;
+    let unreferenced = store.put(&mut &b"unreferenced"[..])?;
+    let missing = "0".repeat(64);
+
+    {
+        let mut conn = db.get()?;
+        for (i, hash) in [&referenced.hash, &missing].iter().enumerate() {
+            Asset {
+                url: format!("https://example.com/{}.png", i),
+                hash: hash.to_string(),
+                path: blob_path(hash),
+                size_bytes: 10,
+                content_type: "image/png".into()
+            }.create_new(&mut *conn)?;
+        }
+    }
+
+    let report = verify_blobs(&db, &store)?;
+    assert!(report.corrupt.is_empty());
+    assert_eq!(report.missing, vec![missing.clone()]);
+
+    std::fs::write(dir.join(&referenced.path), b"tampered with")?;
+    let report = verify_blobs(&db, &store)?;
+    assert_eq!(report.corrupt, vec![referenced.hash.clone()]);
+
+    // Blobs that were only just written are left alone
+    assert!(collect_garbage(&db, &store, chrono::Duration::hours(1))?.is_empty());
+
+    let deleted = collect_garbage(&db, &store, chrono::Duration::zero())?;
+    assert_eq!(deleted, vec![unreferenced.hash]);
+    let left: Vec<_> = store.list()?.into_iter().map(|b| b.hash).collect();
+    assert_eq!(left, vec![referenced.hash]);
+
+    Ok(())
+}
+
+#[test]
+fn track_audio() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let store = client.rocket().state::<SharedBlobStore>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+
+    let track = Track::default();
+    let audio: Vec<u8> = (0..100).collect();
+
+    {
+        let mut conn = db.get()?;
+        track.create_new(&mut *conn, &SoundCloudUser::default())?;
+
+        let blob = store.put(&mut &audio[..])?;
+        TrackAudio {
+            track_id: track.track_id,
+            hash: blob.hash,
+            path: blob.path,
+            size_bytes: blob.size_bytes,
+            content_type: "audio/mpeg".into()
+        }.create_new(&mut *conn)?;
+    }
+
+    let url = format!("/api/tracks/{}/audio", track.track_id);
+
+    // The track isn't in the user's archive yet
+    let mut response = client
+        .get(url.clone())
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    let err: Value = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(err.as_str().unwrap(), "TrackNotFound");
+
+    {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+        user.record_likes(&mut *conn, &liked_just_now(&[track.track_id]))?;
+    }
+
+    let mut response = client
+        .get(url.clone())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::new("audio", "mpeg")));
+    assert_eq!(response.body_bytes().unwrap(), audio);
+
+    let mut response = client
+        .get(url.clone())
+        .header(Header::new("Range", "bytes=10-19"))
+        .dispatch();
+    assert_eq!(response.status(), Status::PartialContent);
+    assert_eq!(response.headers().get_one("Content-Range"), Some("bytes 10-19/100"));
+    assert_eq!(response.body_bytes().unwrap(), &audio[10..20]);
+
+    let response = client
+        .get(url)
+        .header(Header::new("Range", "bytes=100-"))
+        .dispatch();
+    assert_eq!(response.status(), Status::RangeNotSatisfiable);
+
+    let response = client
+        .get("/api/tracks/1/audio")
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    Ok(())
+}
+
+#[test]
+fn mirrored_assets() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let store = client.rocket().state::<SharedBlobStore>().unwrap();
+    setup_test_user(&client)?;
+
+    let track = Track::default();
+    let sc_user = SoundCloudUser::default();
+    let artwork_url = track.artwork_url.clone().unwrap();
+    let avatar_url = sc_user.avatar_url.clone().unwrap();
+
+    let hash = {
+        let mut conn = db.get()?;
+        track.create_new(&mut *conn, &sc_user)?;
+
+        let blob = store.put(&mut &b"not really a png"[..])?;
+        Asset {
+            url: artwork_url.clone(),
+            hash: blob.hash.clone(),
+            path: blob.path,
+            size_bytes: blob.size_bytes,
+            content_type: "image/png".into()
+        }.create_new(&mut *conn)?;
+
+        let unmirrored = Asset::unmirrored_urls(
+            &mut conn,
+            &[artwork_url.clone(), avatar_url.clone(), avatar_url.clone()]
+        )?;
+        assert_eq!(unmirrored, vec![avatar_url]);
+
+        blob.hash
+    };
+
+    let mut response = client
+        .get(format!("/api/track-info/{}", track.track_id))
+        .dispatch();
+    assert_eq!(response.status().class(), StatusClass::Success);
+
+    let track_info: TrackInfoLong = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(track_info.artwork_url, Some(artwork_url));
+    assert_eq!(track_info.local_artwork_url, Some(format!("/api/assets/{}", hash)));
+    assert_eq!(track_info.local_avatar_url, None);
+
+    let mut response = client
+        .get(track_info.local_artwork_url.unwrap())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::PNG));
+    assert_eq!(response.body_bytes().unwrap(), b"not really a png");
+
+    for bad_hash in vec!["..%2F..%2Fetc%2Fpasswd".to_string(), "abc".into(), "0".repeat(64)] {
+        let response = client
+            .get(format!("/api/assets/{}", bad_hash))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn exports() -> Result<(), Error> {
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let store = client.rocket().state::<SharedBlobStore>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+
+    let response = client
+        .get("/api/export")
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let track = Track::default();
+    let sc_user = SoundCloudUser::default();
+    let playlist = Playlist::default();
+
+    let (job, audio_hash) = {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+
+        track.create_new(&mut *conn, &sc_user)?;
+        playlist.create_new(&mut *conn, &sc_user)?;
+        user.record_likes(&mut *conn, &liked_just_now(&[track.track_id]))?;
+        user.record_playlists(&mut *conn, &[(playlist.playlist_id, PlaylistRelation::Owned)])?;
+
+        let audio = store.put(&mut &b"some audio"[..])?;
+        TrackAudio {
+            track_id: track.track_id,
+            hash: audio.hash.clone(),
+            path: audio.path,
+            size_bytes: audio.size_bytes,
+            content_type: "audio/mpeg".into()
+        }.create_new(&mut *conn)?;
+
+        // The artwork and the avatar are the same image
+        let image = store.put(&mut &b"not really a png"[..])?;
+        for url in vec![track.artwork_url.clone().unwrap(), sc_user.avatar_url.clone().unwrap()] {
+            Asset {
+                url,
+                hash: image.hash.clone(),
+                path: image.path.clone(),
+                size_bytes: image.size_bytes,
+                content_type: "image/png".into()
+            }.create_new(&mut *conn)?;
+        }
+
+        let job = ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Export, None, None)?;
+        (job, audio.hash)
+    };
+    export_archive(db, &**store, &job, &AtomicBool::new(false))?;
+
+    let mut response = client
+        .get("/api/export")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::new("application", "gzip")));
+    let bundle = response.body_bytes().unwrap();
+
+    let mut files = HashMap::new();
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(&bundle[..]));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let mut contents = vec![];
+        entry.read_to_end(&mut contents)?;
+        files.insert(path, contents);
+    }
+
+    let manifest: ExportManifest = serde_json::from_slice(&files["manifest.json"])?;
+    assert_eq!(manifest.schema_version, EXPORT_SCHEMA_VERSION);
+    assert_eq!(manifest.user.username, rinfo.username);
+    assert_eq!(manifest.media.len(), 3);
+    assert_eq!(files[&format!("media/{}", audio_hash)], b"some audio");
+    // Five JSON files, the audio and the image (only once)
+    assert_eq!(files.len(), 7);
+
+    let likes: Vec<ExportedLike> = serde_json::from_slice(&files["likes.json"])?;
+    assert_eq!(likes.iter().map(|l| l.track_id).collect::<Vec<_>>(), vec![track.track_id]);
+
+    let tracks: Vec<TrackInfoLong> = serde_json::from_slice(&files["tracks.json"])?;
+    assert_eq!(tracks.len(), 1);
+    assert_eq!(tracks[0].brief_info.title, track.title);
+
+    let playlists: Vec<ExportedPlaylist> = serde_json::from_slice(&files["playlists.json"])?;
+    assert_eq!(playlists.len(), 1);
+    assert_eq!(playlists[0].relation, PlaylistRelation::Owned);
+    assert_eq!(playlists[0].info.track_ids, playlist.track_ids);
+
+    let artists: Vec<ScUserInfo> = serde_json::from_slice(&files["artists.json"])?;
+    assert_eq!(artists.iter().map(|a| a.sc_user_id).collect::<Vec<_>>(), vec![sc_user.sc_user_id]);
+
+    // Interrupted downloads can be picked back up
+    let mut response = client
+        .get("/api/export")
+        .header(Header::new("Range", "bytes=10-"))
+        .dispatch();
+    assert_eq!(response.status(), Status::PartialContent);
+    assert_eq!(response.body_bytes().unwrap(), &bundle[10..]);
+
+    Ok(())
+}
+
+#[test]
+fn imports() -> Result<(), Error> {
+    let track = Track::default();
+    let sc_user = SoundCloudUser::default();
+    let playlist = Playlist::default();
+
+    // A bundle exported from another deployment
+    let bundle = {
+        let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+        let db = client.rocket().state::<DbPool>().unwrap();
+        let store = client.rocket().state::<SharedBlobStore>().unwrap();
+        let rinfo = setup_test_user(&client)?;
+
+        let job = {
+            let mut conn = db.get()?;
+            let user = User::load_username(&mut conn, &rinfo.username)?;
+
+            track.create_new(&mut *conn, &sc_user)?;
+            playlist.create_new(&mut *conn, &sc_user)?;
+            user.record_likes(&mut *conn, &liked_just_now(&[track.track_id]))?;
+            user.record_playlists(&mut *conn, &[(playlist.playlist_id, PlaylistRelation::Owned)])?;
+
+            let audio = store.put(&mut &b"some audio"[..])?;
+            TrackAudio {
+                track_id: track.track_id,
+                hash: audio.hash,
+                path: audio.path,
+                size_bytes: audio.size_bytes,
+                content_type: "audio/mpeg".into()
+            }.create_new(&mut *conn)?;
+
+            ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Export, None, None)?
+        };
+        export_archive(db, &**store, &job, &AtomicBool::new(false))?;
+
+        client.get("/api/export").dispatch().body_bytes().unwrap()
+    };
+
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
+    let store = client.rocket().state::<SharedBlobStore>().unwrap();
+    let rinfo = setup_test_user(&client)?;
+    let all_added = ImportCounts { added: 1, existing: 0 };
+
+    let mut response = client
+        .post("/api/import?dry_run=true")
+        .body(&bundle)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let report: ImportReport = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(report, ImportReport {
+        dry_run: true,
+        tracks: ImportCounts { added: 1, existing: 0 },
+        playlists: ImportCounts { added: 1, existing: 0 },
+        artists: ImportCounts { added: 1, existing: 0 },
+        likes: ImportCounts { added: 1, existing: 0 },
+        media: ImportCounts { added: 1, existing: 0 }
+    });
+
+    // Nothing was actually imported
+    {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+        assert!(user.liked_track_ids(&mut *conn)?.is_empty());
+        assert!(store.list()?.is_empty());
+    }
+
+    // The import was recorded as a job
+    let mut response = client
+        .get("/api/scrape-jobs")
+        .dispatch();
+    let jobs: Vec<ScrapeJobInfo> = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].mode, ScrapeMode::Import);
+    assert_eq!(jobs[0].status, ScrapeJobStatus::Succeeded);
+
+    let mut response = client
+        .post("/api/import")
+        .body(&bundle)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let report: ImportReport = serde_json::from_str(&response.body_string().unwrap())?;
+    assert!(!report.dry_run);
+    assert_eq!(report.tracks, all_added);
+    assert_eq!(report.likes, all_added);
+    assert_eq!(report.media, all_added);
+
+    {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+        assert_eq!(user.liked_track_ids(&mut *conn)?, vec![track.track_id]);
+        assert_eq!(user.playlist_ids(&mut *conn)?, vec![playlist.playlist_id]);
+        assert_eq!(Playlist::load_id(&mut conn, playlist.playlist_id)?, playlist);
+    }
+
+    let mut response = client
+        .get(format!("/api/tracks/{}/audio", track.track_id))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.body_bytes().unwrap(), b"some audio");
+
+    // Imported counts aren't current, so they don't go into the stats history
+    {
+        let mut conn = db.get()?;
+        let snapshots: i64 = conn.query_one(
+            "SELECT COUNT(*) FROM track_stats_snapshots WHERE track_id = $1",
+            &[&track.track_id]
+        )?.get(0);
+        assert_eq!(snapshots, 0);
+
+        // Scraped since the bundle was made
+        Track { title: "Renamed".into(), ..track.clone() }.create_new(&mut *conn, &sc_user)?;
+    }
+
+    // Importing the same bundle again doesn't add anything, or overwrite
+    // anything newer
+    let mut response = client
+        .post("/api/import")
+        .body(&bundle)
+        .dispatch();
+    let report: ImportReport = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(report.tracks, ImportCounts { added: 0, existing: 1 });
+    assert_eq!(report.likes, ImportCounts { added: 0, existing: 1 });
+    assert_eq!(report.media, ImportCounts { added: 0, existing: 1 });
+    {
+        let mut conn = db.get()?;
+        assert_eq!(Track::load_id(&mut conn, track.track_id)?.title, "Renamed");
+    }
+
+    // A likes dump made by orange-zest
+    let dump = serde_json::json!({
+        "collections": [{
+            "created_at": "2020/05/01 12:00:00 +0000",
+            "track": {
+                "id": 5678,
+                "user_id": sc_user.sc_user_id,
+                "duration": 1000,
+                "created_at": "2020/04/01 12:00:00 +0000",
+                "title": "Dumped Track",
+                "permalink_url": "https://dumped.track",
+                "user": {
+                    "id": sc_user.sc_user_id,
+                    "username": sc_user.username,
+                    "permalink_url": sc_user.permalink_url
+                }
+            }
+        }]
+    });
+    let mut response = client
+        .post("/api/import")
+        .header(ContentType::JSON)
+        .body(dump.to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let report: ImportReport = serde_json::from_str(&response.body_string().unwrap())?;
+    assert_eq!(report.tracks, all_added);
+    assert_eq!(report.artists, ImportCounts { added: 0, existing: 1 });
+
+    {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+        // Imported likes go behind the ones that were already there
+        assert_eq!(user.liked_track_ids(&mut *conn)?, vec![track.track_id, 5678]);
+    }
+
+    // Anything else is rejected
+    let mut response = client
+        .post("/api/import")
+        .body("not an archive")
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    let err: Value = serde_json::from_str(&response.body_string().unwrap())?;
+    assert!(err["InvalidImport"].is_string());
+
+    let mut response = client
+        .post("/api/import")
+        .body(r#"{"collections": [{"track": {"id": 1}}]}"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // Imports take up the user's job slot like any other job
+    let mut job = {
+        let mut conn = db.get()?;
+        let user = User::load_username(&mut conn, &rinfo.username)?;
+        ScrapeJob::create_new(&mut conn, user.user_id, ScrapeMode::Full, None, None)?
+    };
+    let response = client
+        .post("/api/import")
+        .body(&bundle)
+        .dispatch();
+    assert_eq!(response.status(), Status::Conflict);
+
+    {
+        let mut conn = db.get()?;
+        job.mark_finished(&mut conn, None)?;
+    }
+    let response = client
+        .post("/api/import")
+        .body(&bundle)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    Ok(())
+}
+
+#[test]
+fn migrate_database_from_before_migrations() -> Result<(), Error> {
+    let mut db_client = test_db_config().connect(NoTls)?;
+
+    // The tables as the very first version of the backend created them
+    db_client.batch_execute("
+        CREATE TABLE users (
+            user_id             SERIAL PRIMARY KEY,
+            username            TEXT NOT NULL UNIQUE,
+            hash                TEXT NOT NULL,
+            sc_oauth_token      TEXT,
+            sc_client_id        TEXT,
+            liked_track_ids     BIGINT[] NOT NULL,
+            playlist_ids        BIGINT[] NOT NULL
+        );
+
+        CREATE TABLE soundcloudusers (
+            sc_user_id      BIGINT PRIMARY KEY,
+            avatar_url      TEXT,
+            full_name       TEXT NOT NULL,
+            username        TEXT NOT NULL,
+            permalink_url   TEXT NOT NULL
+        );
+
+        CREATE TABLE tracks (
+            track_id        BIGINT PRIMARY KEY,
+            sc_user_id      BIGINT NOT NULL references soundcloudusers(sc_user_id),
+            length_ms       BIGINT NOT NULL,
+            created_at      TEXT NOT NULL,
+            title           TEXT NOT NULL,
+            description     TEXT NOT NULL,
+            likes_count     BIGINT NOT NULL,
+            playback_count  BIGINT NOT NULL,
+            artwork_url     TEXT,
+            permalink_url   TEXT NOT NULL,
+            download_url    TEXT
+        );
+
+        CREATE TABLE playlists (
+            playlist_id     BIGINT PRIMARY KEY,
+            sc_user_id      BIGINT NOT NULL references soundcloudusers(sc_user_id),
+            track_ids       BIGINT[] NOT NULL,
+            num_tracks      BIGINT NOT NULL,
+            length_ms       BIGINT NOT NULL,
+            created_at      TEXT NOT NULL,
+            title           TEXT NOT NULL,
+            permalink_url   TEXT NOT NULL,
+            description     TEXT NOT NULL,
+            likes_count     BIGINT NOT NULL,
+            is_album        BOOLEAN NOT NULL
+        );
+
+        INSERT INTO soundcloudusers VALUES (1, NULL, 'Someone', 'someone', 'https://sc.com/someone');
+        INSERT INTO tracks VALUES
+            (1, 1, 1000, '2019-09-10T16:07:05Z', 'One', '', 0, 0, NULL, 'https://sc.com/1', NULL),
+            (2, 1, 1000, '2019/09/10 16:07:05 +0000', 'Two', '', 0, 0, NULL, 'https://sc.com/2', NULL),
+            (3, 1, 1000, '2019-09-10T16:07:05Z', 'Three', '', 0, 0, NULL, 'https://sc.com/3', NULL);
+        INSERT INTO playlists VALUES
+            (10, 1, '{3, 1}', 2, 2000, '2019-09-10T16:07:05Z', 'Mix', 'https://sc.com/10', '', 0, false);
+        INSERT INTO users (username, hash, liked_track_ids, playlist_ids)
+        VALUES ('olduser', 'oldhash', '{1, 2}', '{10}');
+    ")?;
+
+    run_migrations(&mut db_client)?;
+    // Running them again is a no-op
+    run_migrations(&mut db_client)?;
+
+    let versions: Vec<i32> = db_client
+        .query("SELECT version FROM schema_migrations ORDER BY version", &[])?
+        .iter()
+        .map(|r| r.get(0))
+        .collect();
+    assert_eq!(versions, (1..=14).collect::<Vec<i32>>());
+
+    let user = User::load_username(&mut db_client, "olduser")?;
+    assert_eq!(user.scrape_schedule, ScrapeSchedule::Off);
+
+    let mut liked_track_ids = user.liked_track_ids(&mut db_client)?;
+    liked_track_ids.sort();
+    assert_eq!(liked_track_ids, vec![1, 2]);
+    assert_eq!(user.playlist_ids(&mut db_client)?, vec![10]);
+    assert_eq!(Playlist::load_id(&mut db_client, 10)?.track_ids, vec![3, 1]);
+
+    let created_at: DateTime<Utc> = "2019-09-10T16:07:05Z".parse().unwrap();
+    assert_eq!(Track::load_id(&mut db_client, 1)?.created_at, created_at);
+    assert_eq!(Track::load_id(&mut db_client, 2)?.created_at, created_at);
+    assert_eq!(Playlist::load_id(&mut db_client, 10)?.created_at, created_at);
+
+    Ok(())
+}
+
+#[test]
+fn sc_timestamps() {
+    let created_at: DateTime<Utc> = "2019-09-10T16:07:05Z".parse().unwrap();
+
+    assert_eq!(parse_sc_timestamp(Some("2019-09-10T16:07:05Z")).unwrap(), created_at);
+    assert_eq!(parse_sc_timestamp(Some("2019/09/10 16:07:05 +0000")).unwrap(), created_at);
+
+    match parse_sc_timestamp(Some("last tuesday")) {
+        Err(Error::MalformedTimestamp(t)) => assert_eq!(t, "last tuesday"),
+        r => panic!("expected a malformed timestamp error, got {:?}", r)
+    }
+    assert!(parse_sc_timestamp(None).is_err());
+}
+
 #[test]
 fn database_tables() -> Result<(), Error> {
     let mut db_client = test_client()?;
-    create_tables(&mut db_client)?;
+    run_migrations(&mut db_client)?;
 
     let track1 = Track {
         track_id: 847238,
         sc_user_id: 102832,
         length_ms: 4039482,
-        created_at: "2019-09-10T16:07:05Z".into(),
+        created_at: "2019-09-10T16:07:05Z".parse().unwrap(),
         title: "Database Testing Track".into(),
         description: "This is a track for testing the database".into(),
         likes_count: 4838,
@@ -288,7 +1978,7 @@ fn database_tables() -> Result<(), Error> {
         track_id: 1028438,
         sc_user_id: 102832,
         length_ms: 2294884,
-        created_at: "2019-09-17T06:29:59Z".into(),
+        created_at: "2019-09-17T06:29:59Z".parse().unwrap(),
         title: "Sick Banger".into(),
         description: "Does it need explanation??".into(),
         likes_count: 53828,
@@ -312,7 +2002,7 @@ fn database_tables() -> Result<(), Error> {
         track_ids: vec![track1.track_id, track2.track_id],
         num_tracks: 2,
         length_ms: 6334366,
-        created_at: "2019-09-17T06:29:59Z".into(),
+        created_at: "2019-09-17T06:29:59Z".parse().unwrap(),
         title: "My Killer Tunes".into(),
         permalink_url: "https://sadfacefakelink.cupcake".into(),
         description: "This playlist slays dude. Play it in the car".into(),
@@ -323,8 +2013,7 @@ fn database_tables() -> Result<(), Error> {
     sc_user.create_new(&mut db_client)?;
     track1.create_new(&mut db_client, &sc_user)?;
     track2.create_new(&mut db_client, &sc_user)?;
-    // TODO: needs design improvement
-    // playlist.create_new(&mut db_client, &sc_user, &vec![track1.clone(), track2.clone()])?;
+    playlist.create_new(&mut db_client, &sc_user)?;
 
     let loaded_sc_user = SoundCloudUser::load_id(&mut db_client, sc_user.sc_user_id)?;
     let loaded_track1 = Track::load_id(&mut db_client, track1.track_id)?;
@@ -341,8 +2030,8 @@ fn database_tables() -> Result<(), Error> {
 
 #[test]
 fn auth_creds() -> Result<(), Error> {
-    let client = HttpClient::new(rocket(test_client()?)?).unwrap();
-    let db = client.rocket().state::<DbClient>().unwrap();
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
 
     let response = client
         .post("/api/set-auth-creds")
@@ -379,7 +2068,7 @@ fn auth_creds() -> Result<(), Error> {
     assert_eq!(response.status().class(), StatusClass::Success);
 
     {
-        let mut conn = db.lock().unwrap();
+        let mut conn = db.get()?;
         let user = User::load_id(&mut conn, 1)?;
 
         assert_eq!(user.sc_oauth_token.unwrap(), auth_creds.oauth_token);
@@ -391,7 +2080,7 @@ fn auth_creds() -> Result<(), Error> {
 
 #[test]
 fn login_flow() -> Result<(), Error> {
-    let client = HttpClient::new(rocket(test_client()?)?).unwrap();
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
 
     let rinfo = RegisterInfo {
         username: "testusername".into(),
@@ -436,7 +2125,7 @@ fn login_flow() -> Result<(), Error> {
 // TODO: add way to create tests that do not set up database connection
 #[test]
 fn non_existent_api_route() -> Result<(), Error> {
-    let client = HttpClient::new(rocket(test_client()?)?).unwrap();
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
     setup_test_user(&client)?;
 
     // Make request to API get route that doesn't exist
@@ -463,7 +2152,7 @@ fn non_existent_api_route() -> Result<(), Error> {
 // Test trying to log in with a username that doesn't exist
 #[test]
 fn login_nonexistent_username() -> Result<(), Error> {
-    let client = HttpClient::new(rocket(test_client()?)?).unwrap();
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
 
     let mut response = client
         .post("/api/login")
@@ -485,7 +2174,7 @@ fn login_nonexistent_username() -> Result<(), Error> {
 
 #[test]
 fn cannot_create_with_same_username() -> Result<(), Error> {
-    let client = HttpClient::new(rocket(test_client()?)?).unwrap();
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
 
     let rinfo = RegisterInfo {
         username: "testusername".into(),
@@ -517,7 +2206,7 @@ fn cannot_create_with_same_username() -> Result<(), Error> {
 
 #[test]
 fn can_create_with_same_password() -> Result<(), Error> {
-    let client = HttpClient::new(rocket(test_client()?)?).unwrap();
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
     
     let common_password = "testpass";
     let rinfo = RegisterInfo {
@@ -551,8 +2240,8 @@ fn can_create_with_same_password() -> Result<(), Error> {
 #[test]
 #[ignore]
 fn entire_flow_live_site() -> Result<(), Error> {
-    let client = HttpClient::new(rocket(test_client()?)?).unwrap();
-    let db = client.rocket().state::<DbClient>().unwrap();
+    let client = HttpClient::new(rocket(test_pool()?, test_store()?)?).unwrap();
+    let db = client.rocket().state::<DbPool>().unwrap();
     SSE.spawn("[::1]:3000".parse().unwrap());
     let rinfo = setup_test_user(&client)?;
 
@@ -608,7 +2297,7 @@ fn entire_flow_live_site() -> Result<(), Error> {
     
     // Database stuff, not relevant to frontend
     {
-        let mut conn = db.lock().unwrap();
+        let mut conn = db.get()?;
         let track_count: i64 = conn.query_one("SELECT COUNT(track_id) FROM tracks", &[])?.get(0);
         let playlist_count: i64 = conn.query_one("SELECT COUNT(playlist_id) FROM playlists", &[])?.get(0);
 
@@ -616,8 +2305,8 @@ fn entire_flow_live_site() -> Result<(), Error> {
         assert_eq!(playlist_count, num_recent_playlists);
 
         let loaded_user = User::load_username(&mut conn, &rinfo.username)?;
-        assert_eq!(loaded_user.liked_track_ids.len() as i64, num_recent_likes);
-        assert_eq!(loaded_user.playlist_ids.len() as i64, num_recent_playlists);
+        assert_eq!(loaded_user.liked_track_ids(&mut *conn)?.len() as i64, num_recent_likes);
+        assert_eq!(loaded_user.playlist_ids(&mut *conn)?.len() as i64, num_recent_playlists);
     }
 
     // This is how you access likes / track information after scraping has finished
@@ -626,7 +2315,8 @@ fn entire_flow_live_site() -> Result<(), Error> {
         .dispatch();
     assert_eq!(response.status().class(), StatusClass::Success);
     
-    let tracks_brief: Vec<TrackInfoBrief> = serde_json::from_reader(response.body().unwrap().into_inner())?;
+    let tracks_brief: Page<TrackInfoBrief> = serde_json::from_reader(response.body().unwrap().into_inner())?;
+    let tracks_brief = tracks_brief.items;
     assert_eq!(tracks_brief.len() as i64, num_recent_likes);
 
     let mut response = client   
@@ -0,0 +1,52 @@
+use super::*;
+
+use std::sync::atomic::AtomicBool;
+
+/// Rechecks every track in the archive of the given job's user against
+/// SoundCloud, recording whether each one is still available.
+///
+/// Each result is stored as soon as it comes in, so whatever was checked
+/// before the job failed or was cancelled is kept. A "VerificationProgress" SSE
+/// event is sent after each track.
+pub fn verify_archived_tracks(
+    db: &DbPool,
+    job: &ScrapeJob,
+    cancelled: &AtomicBool
+) -> Result<(), Error> {
+    let (user, track_ids) = {
+        let mut conn = db.get()?;
+        let user = User::load_id(&mut conn, job.user_id)?;
+        let track_ids = Track::archived_ids_for_user(&mut conn, &user)?;
+
+        (user, track_ids)
+    };
+    let api = ScApi::for_user(&user)?;
+
+    for (i, track_id) in track_ids.iter().enumerate() {
+        check_cancelled(cancelled)?;
+        let availability = api.track_availability(*track_id)?;
+
+        {
+            let mut conn = db.get()?;
+            Track::set_availability(&mut conn, *track_id, availability)?;
+        }
+
+        // We don't really care about errors here
+        let _ = SSE.push(
+            user.user_id,
+            "update",
+            &SseEvent::VerificationProgress {
+                checked: i + 1,
+                total: track_ids.len()
+            }
+        );
+    }
+
+    let _ = SSE.push(
+        user.user_id,
+        "update",
+        &SseEvent::Complete
+    );
+
+    Ok(())
+}
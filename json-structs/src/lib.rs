@@ -1,5 +1,8 @@
 use serde_derive::{Deserialize, Serialize};
 use orange_zest::events::*;
+use chrono::{DateTime, Duration, Utc};
+
+use std::str::FromStr;
 
 /// The events sent via Server Sent Events are the only things not in this file.
 ///
@@ -25,7 +28,38 @@ pub enum SseEvent<'a> {
     LikesScrapingEvent(LikesZestingEvent),
     PlaylistsScrapingEvent(PlaylistsZestingEvent<'a>),
     /// Scraping has finished
-    Complete
+    Complete,
+    /// Scraping was cancelled before it finished; nothing it fetched was stored
+    Cancelled,
+    /// A track's availability on SoundCloud was rechecked
+    VerificationProgress {
+        /// How many tracks have been checked so far
+        checked: usize,
+        /// How many tracks will be checked in total
+        total: usize
+    },
+    /// A track's audio was downloaded, or turned out not to be available
+    AudioArchivingProgress {
+        /// How many tracks have been dealt with so far
+        processed: usize,
+        /// How many tracks will be dealt with in total
+        total: usize
+    },
+    /// A media file was added to an export bundle
+    ExportProgress {
+        /// How many media files have been added so far
+        added: usize,
+        /// How many media files will be added in total
+        total: usize
+    },
+    /// A track, playlist or media file was imported (or checked, if the
+    /// import is a dry run)
+    ImportProgress {
+        /// How many things have been imported so far
+        processed: usize,
+        /// How many things will be imported in total
+        total: usize
+    }
 }
 
 /// Post this from the web client to provide credentials with which to get data
@@ -68,8 +102,8 @@ pub struct TrackInfoBrief {
     pub track_id: i64,
     /// The length of the track in milliseconds
     pub length_ms: i64,
-    /// When the track was uploaded to SoundCloud as a date-time string
-    pub created_at: String,
+    /// When the track was uploaded to SoundCloud
+    pub created_at: DateTime<Utc>,
     /// The name of the track
     pub title: String,
     /// The number of times the track was played on SoundCloud
@@ -78,7 +112,191 @@ pub struct TrackInfoBrief {
     /// The id of the SoundCloud user that uploaded this track
     pub sc_user_id: i64,
     /// The user's display name
-    pub username: String
+    pub username: String,
+
+    /// Whether the track could still be found on SoundCloud when it was last
+    /// checked
+    pub availability: TrackAvailability,
+    /// When the track's availability was last checked
+    ///
+    /// This is `None` if it has never been checked.
+    pub last_verified_at: Option<DateTime<Utc>>
+}
+
+/// Whether a track can still be found on SoundCloud.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum TrackAvailability {
+    Available,
+    /// The track was deleted by its uploader
+    Deleted,
+    /// The track was made private by its uploader
+    Private
+}
+
+impl TrackAvailability {
+    /// The name this availability is stored under in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrackAvailability::Available => "available",
+            TrackAvailability::Deleted => "deleted",
+            TrackAvailability::Private => "private"
+        }
+    }
+}
+
+impl FromStr for TrackAvailability {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "available" => Ok(TrackAvailability::Available),
+            "deleted" => Ok(TrackAvailability::Deleted),
+            "private" => Ok(TrackAvailability::Private),
+            _ => Err(())
+        }
+    }
+}
+
+/// Which of a user's liked tracks to list.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum LikeFilter {
+    /// Tracks that are currently liked on SoundCloud
+    Current,
+    /// Tracks that used to be liked but were unliked on SoundCloud since
+    Removed,
+    /// Both of the above
+    All
+}
+
+impl FromStr for LikeFilter {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "current" => Ok(LikeFilter::Current),
+            "removed" => Ok(LikeFilter::Removed),
+            "all" => Ok(LikeFilter::All),
+            _ => Err(())
+        }
+    }
+}
+
+/// What to sort a user's liked tracks by.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum LikeSort {
+    /// When the track was liked
+    LikedAt,
+    /// When the track was uploaded to SoundCloud
+    CreatedAt,
+    /// How many times the track has been played
+    PlaybackCount,
+    /// How long the track is
+    LengthMs,
+    /// The name of the track
+    Title
+}
+
+impl FromStr for LikeSort {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "liked_at" => Ok(LikeSort::LikedAt),
+            "created_at" => Ok(LikeSort::CreatedAt),
+            "playback_count" => Ok(LikeSort::PlaybackCount),
+            "length_ms" => Ok(LikeSort::LengthMs),
+            "title" => Ok(LikeSort::Title),
+            _ => Err(())
+        }
+    }
+}
+
+/// Which way to sort a list.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum SortOrder {
+    Ascending,
+    Descending
+}
+
+impl SortOrder {
+    /// The SQL keyword for this order
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            SortOrder::Ascending => "ASC",
+            SortOrder::Descending => "DESC"
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(SortOrder::Ascending),
+            "desc" => Ok(SortOrder::Descending),
+            _ => Err(())
+        }
+    }
+}
+
+/// One page of a longer list.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Page<T> {
+    /// The items on this page
+    pub items: Vec<T>,
+    /// How many items there are on all pages combined
+    pub total: i64,
+    /// Pass this as the `cursor` to get the next page, if there is one
+    pub next_cursor: Option<String>
+}
+
+/// How a playlist in a user's archive relates to the user.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum PlaylistRelation {
+    /// The user made the playlist
+    Owned,
+    /// The user liked someone else's playlist
+    Liked,
+    /// The playlist was archived before we kept track of this
+    Unknown
+}
+
+impl PlaylistRelation {
+    /// The name this relation is stored under in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlaylistRelation::Owned => "owned",
+            PlaylistRelation::Liked => "liked",
+            PlaylistRelation::Unknown => "unknown"
+        }
+    }
+}
+
+impl FromStr for PlaylistRelation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "owned" => Ok(PlaylistRelation::Owned),
+            "liked" => Ok(PlaylistRelation::Liked),
+            "unknown" => Ok(PlaylistRelation::Unknown),
+            _ => Err(())
+        }
+    }
+}
+
+/// A track in a user's like history.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LikeHistoryEntry {
+    /// The track that was liked
+    pub track: TrackInfoBrief,
+    /// When a scrape first saw the track among the user's likes
+    pub first_seen: DateTime<Utc>,
+    /// When a scrape first noticed that the track was no longer liked
+    ///
+    /// This is `None` if the track is still liked.
+    pub removed_at: Option<DateTime<Utc>>
 }
 
 /// Detailed information about a track.
@@ -105,7 +323,23 @@ pub struct TrackInfoLong {
     /// The user's full name
     pub full_name: String,
     /// A URL to the user on SoundCloud
-    pub user_permalink_url: String
+    pub user_permalink_url: String,
+
+    /// When the track's metadata was last refreshed by a scrape
+    pub last_refreshed_at: DateTime<Utc>,
+
+    /// Where our copy of the track's album art can be found, if there is one
+    pub local_artwork_url: Option<String>,
+    /// Where our copy of the user's profile image can be found, if there is one
+    pub local_avatar_url: Option<String>
+}
+
+/// The result of looking up one track in a batch.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum TrackInfoLookup {
+    Found(TrackInfoLong),
+    /// The track isn't stored
+    NotFound
 }
 
 /// Summarized information about a playlist.
@@ -117,8 +351,8 @@ pub struct PlaylistInfoBrief {
     pub playlist_id: i64,
     /// The total length of all tracks in the playlist combined in milliseconds
     pub length_ms: i64,
-    /// When the playlist was created on SoundCloud as a date-time string
-    pub created_at: String,
+    /// When the playlist was created on SoundCloud
+    pub created_at: DateTime<Utc>,
     /// The name of the playlist
     pub title: String,
     /// Whether or not this playlist is an album
@@ -156,7 +390,93 @@ pub struct PlaylistInfoLong {
     /// The user's full name
     pub full_name: String,
     /// A URL to the user on SoundCloud
-    pub user_permalink_url: String
+    pub user_permalink_url: String,
+
+    /// When the playlist's metadata was last refreshed by a scrape
+    pub last_refreshed_at: DateTime<Utc>,
+
+    /// Where our copy of the user's profile image can be found, if there is one
+    pub local_avatar_url: Option<String>
+}
+
+/// A track in a playlist.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlaylistTrack {
+    /// Where in the playlist the track is, starting from 0
+    pub position: i32,
+    /// The id of the track
+    pub track_id: i64,
+    /// Information about the track, or `None` if it isn't stored
+    pub track: Option<TrackInfoBrief>
+}
+
+/// A track's play and like counts at the time of one scrape.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TrackStatsSnapshot {
+    /// When the track was scraped
+    pub scraped_at: DateTime<Utc>,
+    /// The number of times the track had been played on SoundCloud
+    pub playback_count: i64,
+    /// The number of times the track had been liked on SoundCloud
+    pub likes_count: i64
+}
+
+/// A playlist's like and track counts at the time of one scrape.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PlaylistStatsSnapshot {
+    /// When the playlist was scraped
+    pub scraped_at: DateTime<Utc>,
+    /// The number of times the playlist had been liked on SoundCloud
+    pub likes_count: i64,
+    /// The number of tracks the playlist had
+    pub num_tracks: i64
+}
+
+/// The kinds of things that can turn up in search results.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum SearchResultKind {
+    Track,
+    Playlist,
+    Artist
+}
+
+impl SearchResultKind {
+    /// The name this kind goes by in the search query
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SearchResultKind::Track => "track",
+            SearchResultKind::Playlist => "playlist",
+            SearchResultKind::Artist => "artist"
+        }
+    }
+}
+
+impl FromStr for SearchResultKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "track" => Ok(SearchResultKind::Track),
+            "playlist" => Ok(SearchResultKind::Playlist),
+            "artist" => Ok(SearchResultKind::Artist),
+            _ => Err(())
+        }
+    }
+}
+
+/// Something in a user's archive that matched a search.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchResult {
+    pub kind: SearchResultKind,
+    /// The track, playlist or SoundCloud user id of the result, depending on
+    /// its kind
+    pub id: i64,
+    /// The title of the track or playlist, or the username of the artist
+    pub name: String,
+    /// A bit of the matching text with the matching words wrapped in `<b>` tags
+    pub snippet: String,
+    /// How well the result matched; higher is better
+    pub rank: f32
 }
 
 /// Information about a SoundCloud user.
@@ -171,5 +491,310 @@ pub struct ScUserInfo {
     /// The user's display name
     pub username: String,
     /// A URL to the user on SoundCloud
-    pub permalink_url: String
+    pub permalink_url: String,
+    /// Where our copy of the user's profile image can be found, if there is one
+    pub local_avatar_url: Option<String>
+}
+
+/// An artist in a user's archive.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArtistSummary {
+    pub info: ScUserInfo,
+    /// How many of the artist's tracks the user currently likes
+    pub num_liked_tracks: i64
+}
+
+/// An artist along with their tracks and playlists in a user's archive.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArtistDetail {
+    pub info: ScUserInfo,
+    /// The artist's tracks that the user currently likes
+    pub liked_tracks: Vec<TrackInfoBrief>,
+    /// The artist's playlists that the user made or liked
+    pub playlists: Vec<PlaylistInfoBrief>
+}
+
+/// The state a scrape job is in.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum ScrapeJobStatus {
+    /// The job has been recorded but has not started running yet
+    Queued,
+    /// The job is currently scraping data from SoundCloud
+    Running,
+    /// The job stopped because of an error
+    Failed,
+    /// The job finished and everything it scraped was stored
+    Succeeded,
+    /// The job was cancelled before it stored anything
+    Cancelled
+}
+
+impl ScrapeJobStatus {
+    /// The name this status is stored under in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScrapeJobStatus::Queued => "queued",
+            ScrapeJobStatus::Running => "running",
+            ScrapeJobStatus::Failed => "failed",
+            ScrapeJobStatus::Succeeded => "succeeded",
+            ScrapeJobStatus::Cancelled => "cancelled"
+        }
+    }
+}
+
+impl FromStr for ScrapeJobStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(ScrapeJobStatus::Queued),
+            "running" => Ok(ScrapeJobStatus::Running),
+            "failed" => Ok(ScrapeJobStatus::Failed),
+            "succeeded" => Ok(ScrapeJobStatus::Succeeded),
+            "cancelled" => Ok(ScrapeJobStatus::Cancelled),
+            _ => Err(())
+        }
+    }
+}
+
+/// How a scrape job decides how much to scrape.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum ScrapeMode {
+    /// Scrape the requested number of recent likes and playlists
+    Full,
+    /// Only scrape likes and playlists that are newer than the newest ones
+    /// already archived
+    Incremental,
+    /// Don't scrape anything; recheck whether the tracks that are already
+    /// archived are still available on SoundCloud
+    Verify,
+    /// Don't scrape anything; download the audio of liked tracks that doesn't
+    /// have its audio archived yet
+    ArchiveAudio,
+    /// Don't scrape anything; bundle up everything that's archived for
+    /// downloading
+    Export,
+    /// Don't scrape anything; import an uploaded export bundle or dump
+    Import
+}
+
+impl ScrapeMode {
+    /// The name this mode is stored under in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScrapeMode::Full => "full",
+            ScrapeMode::Incremental => "incremental",
+            ScrapeMode::Verify => "verify",
+            ScrapeMode::ArchiveAudio => "archive_audio",
+            ScrapeMode::Export => "export",
+            ScrapeMode::Import => "import"
+        }
+    }
+}
+
+impl FromStr for ScrapeMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(ScrapeMode::Full),
+            "incremental" => Ok(ScrapeMode::Incremental),
+            "verify" => Ok(ScrapeMode::Verify),
+            "archive_audio" => Ok(ScrapeMode::ArchiveAudio),
+            "export" => Ok(ScrapeMode::Export),
+            "import" => Ok(ScrapeMode::Import),
+            _ => Err(())
+        }
+    }
+}
+
+/// How often a user's data is scraped automatically.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum ScrapeSchedule {
+    /// Data is only scraped when the user asks for it
+    Off,
+    Daily,
+    Weekly
+}
+
+impl ScrapeSchedule {
+    /// The name this schedule is stored under in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScrapeSchedule::Off => "off",
+            ScrapeSchedule::Daily => "daily",
+            ScrapeSchedule::Weekly => "weekly"
+        }
+    }
+
+    /// The amount of time between two scheduled scrapes, if there are any
+    pub fn interval(&self) -> Option<Duration> {
+        match self {
+            ScrapeSchedule::Off => None,
+            ScrapeSchedule::Daily => Some(Duration::days(1)),
+            ScrapeSchedule::Weekly => Some(Duration::weeks(1))
+        }
+    }
+}
+
+impl FromStr for ScrapeSchedule {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(ScrapeSchedule::Off),
+            "daily" => Ok(ScrapeSchedule::Daily),
+            "weekly" => Ok(ScrapeSchedule::Weekly),
+            _ => Err(())
+        }
+    }
+}
+
+/// Post this from the web client to change how often the logged-in user's data
+/// is scraped automatically.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ScrapeScheduleSettings {
+    pub schedule: ScrapeSchedule
+}
+
+/// Information about the logged-in user's scrape schedule sent to the web client
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ScrapeScheduleInfo {
+    pub schedule: ScrapeSchedule,
+    /// When the next scheduled scrape is due
+    ///
+    /// This is `None` if the schedule is off.
+    pub next_run_at: Option<DateTime<Utc>>
+}
+
+/// Information about a scrape job.
+///
+/// Every request to scrape data creates one of these, so the web client can
+/// look up how a scrape went even if it wasn't listening for SSE events at
+/// the time.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ScrapeJobInfo {
+    /// A unique numeric id for the job
+    pub job_id: i32,
+    /// The state the job is currently in
+    pub status: ScrapeJobStatus,
+    /// How the job decides how much to scrape
+    pub mode: ScrapeMode,
+    /// How many recent likes the job was asked to scrape (`None` means all
+    /// of them)
+    pub num_recent_likes: Option<i64>,
+    /// How many recent playlists the job was asked to scrape (`None` means all
+    /// of them)
+    pub num_recent_playlists: Option<i64>,
+    /// When the job was requested
+    pub created_at: DateTime<Utc>,
+    /// When the job started running
+    pub started_at: Option<DateTime<Utc>>,
+    /// When the job stopped running, successfully or not
+    pub finished_at: Option<DateTime<Utc>>,
+    /// A description of what went wrong if the job failed
+    pub error: Option<String>
+}
+
+/// The version of the layout of export bundles.
+///
+/// Bumped whenever the layout changes in a way that older versions of the
+/// backend couldn't make sense of.
+pub const EXPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Describes an export bundle.
+///
+/// A bundle is a gzipped tarball containing this as `manifest.json`, along
+/// with `likes.json` (a list of `ExportedLike`), `tracks.json` (a list of
+/// `TrackInfoLong`), `playlists.json` (a list of `ExportedPlaylist`),
+/// `artists.json` (a list of `ScUserInfo`) and the media files listed here.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExportManifest {
+    /// The `EXPORT_SCHEMA_VERSION` of the backend that made the bundle
+    pub schema_version: u32,
+    /// When the bundle was made
+    pub exported_at: DateTime<Utc>,
+    /// The user whose archive is in the bundle
+    pub user: UserInfo,
+    /// The archived audio and mirrored images in the bundle
+    pub media: Vec<ExportedMedia>
+}
+
+/// A track in a user's likes, as found in an export bundle.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExportedLike {
+    pub track_id: i64,
+    /// When the user liked the track on SoundCloud, if we know
+    pub liked_at: Option<DateTime<Utc>>,
+    /// When a scrape first saw the track among the user's likes
+    pub first_seen: DateTime<Utc>,
+    /// Where the track is in the user's likes, starting at 0 for the most
+    /// recent one
+    ///
+    /// This is `None` for likes that were removed, and for likes that haven't
+    /// been seen by a scrape since we started keeping track of this.
+    pub position: Option<i32>,
+    /// When a scrape first noticed that the track was no longer liked
+    pub removed_at: Option<DateTime<Utc>>
+}
+
+/// A playlist in a user's archive, as found in an export bundle.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExportedPlaylist {
+    /// How the playlist relates to the user
+    pub relation: PlaylistRelation,
+    pub info: PlaylistInfoLong
+}
+
+/// A media file in an export bundle.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExportedMedia {
+    /// Where the file is in the bundle
+    pub path: String,
+    /// The hex-encoded SHA-256 hash of the file
+    pub hash: String,
+    /// The size of the file in bytes
+    pub size_bytes: i64,
+    /// The MIME type of the file
+    pub content_type: String,
+    /// What the file is
+    pub kind: ExportedMediaKind
+}
+
+/// What a media file in an export bundle is.
+///
+/// The same file can be listed more than once in a bundle's manifest with
+/// different kinds, but it's only included in the bundle once.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ExportedMediaKind {
+    /// The archived audio of the track with the given id
+    TrackAudio { track_id: i64 },
+    /// An image that was mirrored from the given URL
+    Asset { url: String }
+}
+
+/// How many things of one kind an import brought into an archive.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct ImportCounts {
+    /// How many weren't in the archive before
+    pub added: usize,
+    /// How many were in the archive already
+    pub existing: usize
+}
+
+/// What an import changed, or would have changed if it was a dry run.
+///
+/// Tracks and playlists that were in the archive already are only updated if
+/// the import's copy of them is newer. Artists, likes, playlist relations and
+/// media that were in the archive already are left alone.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ImportReport {
+    /// Whether nothing was actually changed
+    pub dry_run: bool,
+    pub tracks: ImportCounts,
+    pub playlists: ImportCounts,
+    pub artists: ImportCounts,
+    pub likes: ImportCounts,
+    /// Archived audio and mirrored images
+    pub media: ImportCounts
 }